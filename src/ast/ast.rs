@@ -1,43 +1,69 @@
-use std::collections::{hash_map, HashMap};
+use std::collections::HashMap;
+use crate::ast::diagnostic::Diagnostic;
+use crate::ast::types::Type;
+use crate::lexer::span::Span;
 use crate::lexer::tokens::{
     Extern,
     Function,
     Ident,
+    Integer,
     Number,
+    Bool,
     Operator,
     If,
     Then,
     Else,
     In,
     For,
+    While,
+    Loop,
+    Break,
+    Continue,
+    Return,
+    Label,
     Let,
-    OpeningBrackets,
+    Mut,
     OpeningParenthesis,
     ClosingParenthesis,
-    ClosingBrackets,
     Delimiter,
+    OpeningBrackets,
+    ClosingBrackets,
     Unary,
     Binary,
     Comma,
+    Colon,
+    Arrow,
 };
-use crate::lexer::tokens::Tokens;
+use crate::lexer::tokens::{Token, Tokens};
 
 pub use self::ASTNode::{
     ExternNode,
     FunctionsNode,
 };
 
-pub use self::Expression::{
+pub use self::ExpressionKind::{
     LiteralExpr,
     VariableExpr,
     UnaryExpr,
     BinaryExpr,
     ConditionalExpr,
     LoopExpr,
+    WhileExpr,
+    BreakExpr,
+    ContinueExpr,
+    ReturnExpr,
     VarExpr,
+    AssignExpr,
+    BlockExpr,
     CallExpr
 };
 
+pub use self::Literal::{
+    IntegerLiteral,
+    FloatLiteral,
+    BoolLiteral
+};
+
 pub use self::FunctionType::{
     Normal,
     UnaryOp,
@@ -56,41 +82,101 @@ pub enum ASTNode {
     FunctionsNode(Functions)
 }
 
+impl ASTNode {
+    pub fn span(&self) -> Span {
+        match *self {
+            ExternNode(ref prototype) => prototype.span,
+            FunctionsNode(ref function) => function.span
+        }
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct Functions {
     pub prototype: Prototype,
-    pub body: Expression
+    pub body: Expression,
+    pub span: Span
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct Prototype {
     pub name: String,
     pub fn_type: FunctionType,
-    pub args: Vec<String>,
+    pub args: Vec<(String, Option<Type>)>,
+    pub ret_type: Option<Type>,
+    pub span: Span
 }
 
+/// Types left out in the source are `None` until the type checker fills them in, `ty`
+/// of an expression is likewise only known after checking.
 #[derive(PartialEq, Clone, Debug)]
-pub enum Expression {
-    LiteralExpr(f64),
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+    pub ty: Option<Type>
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Expression {
+        Expression { kind, span, ty: None }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Literal {
+    IntegerLiteral(i64),
+    FloatLiteral(f64),
+    BoolLiteral(bool)
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ExpressionKind {
+    LiteralExpr(Literal),
     VariableExpr(String),
     UnaryExpr(String, Box<Expression>),
     BinaryExpr(String, Box<Expression>, Box<Expression>),
+    /// `if cond then a else b`. Without `else` it has type `()`, like an `if` whose
+    /// branches are of that type the value of the branch taken is dropped.
     ConditionalExpr {
         cond_expr: Box<Expression>,
         then_expr: Box<Expression>,
-        else_expr: Box<Expression>
+        else_expr: Option<Box<Expression>>
     },
     LoopExpr {
+        label: Option<String>,
         var_name: String,
         start_expr: Box<Expression>,
         end_expr: Box<Expression>,
         step_expr: Box<Expression>,
         body_expr: Box<Expression>
     },
+    /// `while cond { ... }`, or `loop { ... }` when there is no condition. Only `loop`
+    /// can be left with a value by `break`.
+    WhileExpr {
+        label: Option<String>,
+        cond_expr: Option<Box<Expression>>,
+        body_expr: Box<Expression>
+    },
+    /// Leaves the innermost loop, or the one with the label, with a value.
+    BreakExpr {
+        label: Option<String>,
+        value: Option<Box<Expression>>
+    },
+    /// Goes on with the next iteration of the innermost loop or the one with the label.
+    ContinueExpr(Option<String>),
+    /// Leaves the function being evaluated with a value, from wherever it is.
+    ReturnExpr(Box<Expression>),
+    /// Name, type annotation, initial value and whether it is declared `mut` of every
+    /// variable bound for `body_expr`.
     VarExpr {
-        vars: Vec<(String, Expression)>,
+        vars: Vec<(String, Option<Type>, Expression, bool)>,
         body_expr: Box<Expression>
     },
+    /// `name = value`, which gives the assigned value.
+    AssignExpr(String, Box<Expression>),
+    /// Expressions separated by `;` in braces, evaluated in order. The value of the block
+    /// is the value of the last one.
+    BlockExpr(Vec<Expression>),
     CallExpr(String, Vec<Expression>)
 }
 
@@ -101,58 +187,93 @@ pub enum FunctionType {
     BinaryOp(String, i32)
 }
 
-pub type ParsingResult = Result<(Vec<ASTNode>, Vec<Tokens>), String>;
+pub type ParsingResult = Result<(Vec<ASTNode>, Vec<Token>), String>;
+
+pub type RecoveringParsingResult = (Vec<ASTNode>, Vec<Token>, Vec<Diagnostic>);
 
 enum PartParsingResult<T> {
-    Good(T, Vec<Tokens>),
+    Good(T, Vec<Token>),
     NotComplete,
-    Bad(String)
+    Bad(String, Span)
 }
 
-fn error<T>(msg: &str) -> PartParsingResult<T> {
-    Bad(msg.to_string())
+fn error<T>(msg: &str, span: Span) -> PartParsingResult<T> {
+    Bad(msg.to_string(), span)
 }
 
+/// Span from the first to the last of the consumed tokens.
+fn span_of(parsed_tokens: &[Token]) -> Span {
+    match (parsed_tokens.first(), parsed_tokens.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span::default()
+    }
+}
+
+#[derive(Clone)]
 pub struct ParserSettings {
-    op_precedence: HashMap<String, i32>
+    op_precedence: HashMap<String, i32>,
+    /// Label of every loop around the expression being parsed, and whether `break` can
+    /// leave it with a value.
+    loops: Vec<(Option<String>, bool)>
+}
+
+impl ParserSettings {
+    /// Makes the operator `prototype` defines, if it is a `binary` one, known from now on.
+    pub fn add_operator(&mut self, prototype: &Prototype) {
+        if let BinaryOp(ref symbol, precedence) = prototype.fn_type {
+            self.op_precedence.insert(symbol.clone(), precedence);
+        }
+    }
 }
 
+/// Precedence of the built-in binary operators, ordered like in C. These cannot be
+/// redefined by `binary` functions.
+pub const BUILTIN_PRECEDENCE: &[(&str, i32)] = &[
+    ("||", 3),
+    ("&&", 4),
+    ("|", 5),
+    ("^", 6),
+    ("&", 7),
+    ("==", 8),
+    ("!=", 8),
+    ("<", 10),
+    ("<=", 10),
+    (">", 10),
+    (">=", 10),
+    ("<<", 15),
+    (">>", 15),
+    ("+", 20),
+    ("-", 20),
+    ("*", 40),
+    ("/", 40),
+    ("%", 40),
+];
+
+/// The built-in unary operators, which cannot be redefined by `unary` functions either.
+pub const BUILTIN_UNARY_OPERATORS: &[&str] = &["-", "~", "!"];
+
 pub fn default_parser_settings() -> ParserSettings {
     let mut op_precedence = HashMap::new();
     op_precedence.insert("(".to_string(), 100);
     op_precedence.insert(")".to_string(), 100);
-    op_precedence.insert("=".to_string(), 2);
-    op_precedence.insert("<".to_string(), 10);
-    op_precedence.insert("+".to_string(), 20);
-    op_precedence.insert("-".to_string(), 20);
-    op_precedence.insert("*".to_string(), 40);
+    for &(operator, precedence) in BUILTIN_PRECEDENCE {
+        op_precedence.insert(operator.to_string(), precedence);
+    }
 
-    ParserSettings { op_precedence }
+    ParserSettings { op_precedence, loops: Vec::new() }
 }
 
-pub fn parse(tokens: &[Tokens], parsed_tree: &[ASTNode], settings: &mut ParserSettings) -> ParsingResult {
+pub fn parse(tokens: &[Token], parsed_tree: &[ASTNode], settings: &mut ParserSettings) -> ParsingResult {
     let mut rest = tokens.to_vec();
     rest.reverse();
 
     let mut ast = parsed_tree.to_vec();
 
-    loop {
-        let current_token = match rest.last() {
-            Some(token) => token.clone(),
-            None => break
-        };
-
-        let result = match current_token {
-            Function => parse_function(&mut rest, settings),
-            Extern => parse_extern(&mut rest, settings),
-            Delimiter => {rest.pop(); continue}
-            _ => parse_expression(&mut rest, settings)
-        };
-
+    while let Some(result) = parse_next(&mut rest, settings) {
         match result {
             Good(ast_node, _) => ast.push(ast_node),
             NotComplete => break,
-            Bad(message) => return Err(message)
+            Bad(message, span) => return Err(format!("{}: {}", span, message))
         }
     }
 
@@ -160,6 +281,72 @@ pub fn parse(tokens: &[Tokens], parsed_tree: &[ASTNode], settings: &mut ParserSe
     Ok((ast, rest))
 }
 
+/// Like `parse`, but instead of stopping at the first error it records a diagnostic,
+/// skips to the next `;`, `function` or `extern` and carries on, so that the nodes
+/// around a broken one are still returned.
+pub fn parse_recovering(tokens: &[Token], parsed_tree: &[ASTNode], settings: &mut ParserSettings)
+-> RecoveringParsingResult {
+    let mut rest = tokens.to_vec();
+    rest.reverse();
+
+    let mut ast = parsed_tree.to_vec();
+    let mut diagnostics = Vec::new();
+
+    loop {
+        let remaining = rest.len();
+        match parse_next(&mut rest, settings) {
+            Some(Good(ast_node, _)) => ast.push(ast_node),
+            Some(NotComplete) | None => break,
+            Some(Bad(message, span)) => {
+                diagnostics.push(Diagnostic::error(&message, span));
+                // blocks the broken node opened are skipped up to their closing brace
+                let consumed = &tokens[tokens.len() - remaining..tokens.len() - rest.len()];
+                let depth = consumed.iter().fold(0, |depth: usize, token| match token.kind {
+                    OpeningBrackets => depth + 1,
+                    ClosingBrackets => depth.saturating_sub(1),
+                    _ => depth
+                });
+                synchronize(&mut rest, depth);
+            }
+        }
+    }
+
+    rest.reverse();
+    (ast, rest, diagnostics)
+}
+
+fn parse_next(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> Option<PartParsingResult<ASTNode>> {
+    while let Some(Token { kind: Delimiter, .. }) = tokens.last() {
+        tokens.pop();
+    }
+
+    let result = match tokens.last()?.kind {
+        Function => parse_function(tokens, settings),
+        Extern => parse_extern(tokens, settings),
+        _ => parse_expression(tokens, settings)
+    };
+
+    Some(result)
+}
+
+/// Skips to the next `;` outside of the `depth` blocks the parser is in, or to the next
+/// `function` or `extern`.
+fn synchronize(tokens: &mut Vec<Token>, mut depth: usize) {
+    while let Some(token) = tokens.last() {
+        match token.kind {
+            Function | Extern => break,
+            Delimiter if depth == 0 => {
+                tokens.pop();
+                break
+            },
+            OpeningBrackets => depth += 1,
+            ClosingBrackets => depth = depth.saturating_sub(1),
+            _ => ()
+        }
+        tokens.pop();
+    }
+}
+
 macro_rules! parse_try(
     ($function:ident, $tokens:ident, $settings:ident, $parsed_tokens:ident) => (
         parse_try!($function, $tokens, $settings, $parsed_tokens,)
@@ -176,35 +363,40 @@ macro_rules! parse_try(
                 $tokens.extend($parsed_tokens.into_iter());
                 return NotComplete;
             },
-            Bad(message) => return Bad(message)
+            Bad(message, span) => return Bad(message, span)
         }
     )
 );
 
 macro_rules! expect_token (
-    ([ $($token:pat, $value:expr, $result:stmt);+ ] <= $tokens:ident, $parsed_tokens:ident, $error:expr) => (
+    ([ $($token:pat, $result:stmt);+ ] <= $tokens:ident, $parsed_tokens:ident, $error:expr) => (
         match $tokens.pop() {
-            $(
-                Some($token) => {
-                    $parsed_tokens.push($value);
-                    $result
-                },
-             )+
-             None => {
-                 $parsed_tokens.reverse();
-                 $tokens.extend($parsed_tokens.into_iter());
-                 return NotComplete;
-             },
-            _ => return error($error)
+            Some(token) => match token.kind.clone() {
+                $(
+                    $token => {
+                        $parsed_tokens.push(token);
+                        $result
+                    },
+                 )+
+                _ => {
+                    let span = token.span;
+                    $tokens.push(token);
+                    return error($error, span)
+                }
+            },
+            None => {
+                $parsed_tokens.reverse();
+                $tokens.extend($parsed_tokens.into_iter());
+                return NotComplete;
+            }
         }
     );
 
-    ([ $($token:pat, $value:expr, $result:stmt);+ ] else $not_matched:block <= $tokens:ident, $parsed_tokens:ident) => (
-        match $tokens.last().map(|i| {i.clone()}) {
+    ([ $($token:pat, $result:stmt);+ ] else $not_matched:block <= $tokens:ident, $parsed_tokens:ident) => (
+        match $tokens.last().map(|i| i.kind.clone()) {
             $(
                 Some($token) => {
-                    $tokens.pop();
-                    $parsed_tokens.push($value);
+                    $parsed_tokens.push($tokens.pop().unwrap());
                     $result
                 },
              )+
@@ -213,55 +405,57 @@ macro_rules! expect_token (
     )
 );
 
-fn parse_extern(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<ASTNode> {
-    tokens.pop();
-
-    let mut parsed_tokens = vec![Extern];
+fn parse_extern(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<ASTNode> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
     let prototype = parse_try!(parse_prototype, tokens, settings, parsed_tokens);
     Good(ExternNode(prototype), parsed_tokens)
 }
 
-fn parse_function(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<ASTNode> {
-    tokens.pop();
-
-    let mut parsed_tokens = vec!(Function);
+fn parse_function(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<ASTNode> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
     let prototype = parse_try!(parse_prototype, tokens, settings, parsed_tokens);
 
-    match prototype.fn_type {
-        BinaryOp(ref symbol, precedence) => {
-            settings.op_precedence.insert(symbol.clone(), precedence);
-        },
-        _ => ()
-    };
+    settings.add_operator(&prototype);
 
     let body = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
-    Good(FunctionsNode(Functions{prototype, body}), parsed_tokens)
+    let span = span_of(&parsed_tokens);
+    Good(FunctionsNode(Functions{prototype, body, span}), parsed_tokens)
 }
 
-fn parse_prototype(tokens : &mut Vec<Tokens>, _settings : &mut ParserSettings) -> PartParsingResult<Prototype> {
+fn parse_prototype(tokens : &mut Vec<Token>, settings : &mut ParserSettings) -> PartParsingResult<Prototype> {
     let mut parsed_tokens = Vec::new();
 
     let (name, fn_type) = expect_token!([
-            Ident(name), Ident(name.clone()),
+            Ident(name),
             (name, Normal);
-            Unary, Unary, {
+            Unary, {
                 let op = expect_token!([
-                        Operator(op), Operator(op.clone()), op
+                        Operator(op), op
                     ] <= tokens, parsed_tokens, "expected unary operator");
+                if BUILTIN_UNARY_OPERATORS.contains(&op.as_str()) {
+                    return error(&format!("operator {} is built in and cannot be redefined", op),
+                                 parsed_tokens.last().unwrap().span);
+                }
                 ("unary".to_string() + &op, UnaryOp(op))
             };
-        Binary, Binary, {
+        Binary, {
                 let op = expect_token!([
-                        Operator(op), Operator(op.clone()), op
+                        Operator(op), op
                     ] <= tokens, parsed_tokens, "expected binary operator");
+                // assignment is not a binary operator, but cannot be redefined either
+                if op == "=" || BUILTIN_PRECEDENCE.iter().any(|&(builtin, _)| builtin == op) {
+                    return error(&format!("operator {} is built in and cannot be redefined", op),
+                                 parsed_tokens.last().unwrap().span);
+                }
                 let precedence = expect_token!(
-                    [Number(value), Number(value), value as i32]
+                    [Integer(value), value as i32;
+                     Number(value), value as i32]
                     else {30}
                     <= tokens, parsed_tokens);
 
-                if precedence < 1 || precedence > 100 {
-                    return error("invalid precedecnce: must be 1..100");
+                if !(1..=100).contains(&precedence) {
+                    return error("invalid precedecnce: must be 1..100", parsed_tokens.last().unwrap().span);
                 }
 
                 ("binary".to_string() + &op, BinaryOp(op, precedence))
@@ -269,88 +463,129 @@ fn parse_prototype(tokens : &mut Vec<Tokens>, _settings : &mut ParserSettings) -
         ] <= tokens, parsed_tokens, "expected function name in prototype");
 
     expect_token!(
-        [OpeningParenthesis, OpeningParenthesis, ()] <= tokens,
+        [OpeningParenthesis, ()] <= tokens,
         parsed_tokens, "expected '(' in prototype");
 
     let mut args = Vec::new();
     loop {
         expect_token!([
-            Ident(arg), Ident(arg.clone()), args.push(arg.clone());
-            Comma, Comma, continue;
-            ClosingParenthesis, ClosingParenthesis, break
+            Ident(arg), {
+                let ty = parse_try!(parse_type_annotation, tokens, settings, parsed_tokens, Colon);
+                args.push((arg, ty));
+            };
+            Comma, continue;
+            ClosingParenthesis, break
         ] <= tokens, parsed_tokens, "expected ')' in prototype");
     }
 
+    let ret_type = parse_try!(parse_type_annotation, tokens, settings, parsed_tokens, Arrow);
+
+    let span = span_of(&parsed_tokens);
+
     match fn_type {
-        UnaryOp(_) => if args.len() != 1 {
-            return error("invalid of operands for unary operator")
+        UnaryOp(_) if args.len() != 1 => {
+            return error("invalid of operands for unary operator", span)
         },
-        BinaryOp(_, _) => if args.len() != 2 {
-            return error("invalid number of operands for binary operator")
+        BinaryOp(_, _) if args.len() != 2 => {
+            return error("invalid number of operands for binary operator", span)
         },
         _ => ()
     };
 
-    Good(Prototype { name, args, fn_type, }, parsed_tokens)
+    Good(Prototype { name, args, ret_type, fn_type, span }, parsed_tokens)
 }
 
-fn parse_expression(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<ASTNode> {
+/// Parses `<separator> type` if the next token is `separator`, nothing otherwise.
+fn parse_type_annotation(tokens: &mut Vec<Token>, _settings: &mut ParserSettings, separator: Tokens)
+-> PartParsingResult<Option<Type>> {
+    let mut parsed_tokens = Vec::new();
+
+    match tokens.last() {
+        Some(token) if token.kind == separator => parsed_tokens.push(tokens.pop().unwrap()),
+        _ => return Good(None, parsed_tokens)
+    };
+
+    let name = expect_token!(
+        [Ident(name), name] <= tokens, parsed_tokens, "expected type name"
+    );
+
+    match Type::from_name(&name) {
+        Some(ty) => Good(Some(ty), parsed_tokens),
+        None => error(&format!("unknown type {}", name), parsed_tokens.last().unwrap().span)
+    }
+}
+
+fn parse_expression(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<ASTNode> {
     let mut parsed_tokens = Vec::new();
 
     let expression = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
+    let span = expression.span;
     let prototype = Prototype {
         name: "".to_string(),
         args: vec![],
-        fn_type: Normal
+        ret_type: None,
+        fn_type: Normal,
+        span
     };
     let lambda = Functions {
         prototype,
-        body: expression
+        body: expression,
+        span
     };
 
     Good(FunctionsNode(lambda), parsed_tokens)
 }
 
-fn parse_primary_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
-    match tokens.last() {
-        Some(&Ident(_)) => parse_ident_expr(tokens, settings),
-        Some(&Number(_)) => parse_literal_expr(tokens, settings),
-        Some(&If) => parse_conditional_expr(tokens, settings),
-        Some(&For) => parse_loop_expr(tokens, settings),
-        Some(&Let) => parse_let_expr(tokens, settings),
-        Some(&Operator(_)) => parse_unary_expr(tokens, settings),
-        Some(&OpeningParenthesis) => parse_parenthesis_expr(tokens, settings),
-        // Some(&OpeningBrackets) => parse_brackets_expr(tokens, settings),
-        None => return NotComplete,
-        Some(unexpected) => {
+fn parse_primary_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    match tokens.last().map(|token| (&token.kind, token)) {
+        Some((&Ident(_), _)) => parse_ident_expr(tokens, settings),
+        Some((&Integer(_), _)) | Some((&Number(_), _)) | Some((&Bool(_), _)) => {
+            parse_literal_expr(tokens, settings)
+        },
+        Some((&If, _)) => parse_conditional_expr(tokens, settings),
+        Some((&For, _)) => parse_loop_expr(tokens, settings, None),
+        Some((&While, _)) | Some((&Loop, _)) => parse_while_expr(tokens, settings, None),
+        Some((&Label(_), _)) => parse_labeled_expr(tokens, settings),
+        Some((&Break, _)) => parse_break_expr(tokens, settings),
+        Some((&Continue, _)) => parse_continue_expr(tokens, settings),
+        Some((&Return, _)) => parse_return_expr(tokens, settings),
+        Some((&Let, _)) => parse_let_expr(tokens, settings),
+        Some((&Operator(_), _)) => parse_unary_expr(tokens, settings),
+        Some((&OpeningParenthesis, _)) => parse_parenthesis_expr(tokens, settings),
+        Some((&OpeningBrackets, _)) => parse_brackets_expr(tokens, settings),
+        None => NotComplete,
+        Some((_, unexpected)) => {
             error(format!(
                 "Unexpected token: {:?} when expecting an expression",
-                unexpected
-            ).as_str())
+                unexpected.kind
+            ).as_str(), unexpected.span)
         }
     }
 }
 
-fn parse_ident_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+fn parse_ident_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
     let mut parsed_tokens = Vec::new();
 
     let name = expect_token!(
-        [Ident(name), Ident(name.clone()), name] <= tokens,
+        [Ident(name), name] <= tokens,
         parsed_tokens, "identificator expected"
     );
 
     expect_token!(
-        [OpeningParenthesis, OpeningParenthesis, ()]
-        else { return Good(VariableExpr(name), parsed_tokens) }
+        [OpeningParenthesis, ()]
+        else {
+            let span = span_of(&parsed_tokens);
+            return Good(Expression::new(VariableExpr(name), span), parsed_tokens)
+        }
         <= tokens, parsed_tokens
     );
 
     let mut args = Vec::new();
     loop {
         expect_token!(
-            [ClosingParenthesis, ClosingParenthesis, break;
-             Comma, Comma, continue]
+            [ClosingParenthesis, break;
+             Comma, continue]
             else {
                 args.push(parse_try!(parse_expr, tokens, settings, parsed_tokens))
             }
@@ -358,133 +593,153 @@ fn parse_ident_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) ->
         );
     }
 
-    Good(CallExpr(name, args), parsed_tokens)
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(CallExpr(name, args), span), parsed_tokens)
 }
 
-fn parse_literal_expr(tokens: &mut Vec<Tokens>, _settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+fn parse_literal_expr(tokens: &mut Vec<Token>, _settings: &mut ParserSettings) -> PartParsingResult<Expression> {
     let mut parsed_tokens = Vec::new();
 
     let value = expect_token!(
-        [Number(val), Number(val), val] <= tokens, parsed_tokens, "literal expected"
+        [Integer(val), IntegerLiteral(val);
+         Number(val), FloatLiteral(val);
+         Bool(val), BoolLiteral(val)] <= tokens, parsed_tokens, "literal expected"
     );
 
-    Good(LiteralExpr(value), parsed_tokens)
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(LiteralExpr(value), span), parsed_tokens)
 }
 
-fn parse_parenthesis_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
-    tokens.pop();
-
-    let mut parsed_tokens = vec![OpeningParenthesis];
+fn parse_parenthesis_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
     let expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
     expect_token!(
-        [ClosingParenthesis, ClosingParenthesis, ()] <= tokens, parsed_tokens, ""
+        [ClosingParenthesis, ()] <= tokens, parsed_tokens, "expected ')'"
     );
 
-    Good(expr, parsed_tokens)
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(expr.kind, span), parsed_tokens)
 }
 
-/*
-fn parse_brackets_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
-    tokens.pop();
+fn parse_brackets_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
+    let mut exprs = Vec::new();
 
-    let mut parsed_tokens = vec![OpeningBrackets];
-    let expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
+    loop {
+        exprs.push(parse_try!(parse_expr, tokens, settings, parsed_tokens));
 
-    expect_token!(
-        [ClosingBrackets, ClosingBrackets, ()] <= tokens, parsed_tokens, "expected '}'"
-    );
+        expect_token!(
+            [ClosingBrackets, break;
+             Delimiter, ()] <= tokens, parsed_tokens, "expected ';' or '}'"
+        );
+
+        // the last expression may be followed by a ';' too
+        if let Some(Token { kind: ClosingBrackets, .. }) = tokens.last() {
+            parsed_tokens.push(tokens.pop().unwrap());
+            break;
+        }
+    }
 
-    Good(expr, parsed_tokens)
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(BlockExpr(exprs), span), parsed_tokens)
 }
-*/
 
-fn parse_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+fn parse_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
     let mut parsed_tokens = Vec::new();
     let lhs = parse_try!(parse_primary_expr, tokens, settings, parsed_tokens);
     let expr = parse_try!(parse_binary_expr, tokens, settings, parsed_tokens, 0, &lhs);
 
-    Good(expr, parsed_tokens)
+    // assignment binds looser than every binary operator and groups to the right
+    match tokens.last() {
+        Some(Token { kind: Operator(ref op), .. }) if op == "=" => (),
+        _ => return Good(expr, parsed_tokens)
+    }
+
+    let name = match expr.kind {
+        VariableExpr(ref name) => name.clone(),
+        _ => return error("invalid left-hand side of assignment", expr.span)
+    };
+    parsed_tokens.push(tokens.pop().unwrap());
+
+    let value = parse_try!(parse_expr, tokens, settings, parsed_tokens);
+    let span = expr.span.to(value.span);
+    Good(Expression::new(AssignExpr(name, Box::new(value)), span), parsed_tokens)
 }
 
-fn parse_binary_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings, expr_precedence: i32, lhs: &Expression)
+fn parse_binary_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings, expr_precedence: i32, lhs: &Expression)
 -> PartParsingResult<Expression> {
     let mut result = lhs.clone();
     let mut parsed_tokens = Vec::new();
 
-    loop {
-        let (operator, precedence) = match tokens.last() {
-            Some(&Operator(ref op)) => match settings.op_precedence.get(op) {
-                Some(pr) if *pr >= expr_precedence => (op.clone(), *pr),
-                None => return error("unknown operator found"),
-                _ => break
-            },
+    while let Some(Token { kind: Operator(ref op), span }) = tokens.last() {
+        let (operator, precedence) = match settings.op_precedence.get(op) {
+            Some(pr) if *pr >= expr_precedence => (op.clone(), *pr),
+            None if op == "=" => break,
+            None => return error("unknown operator found", *span),
             _ => break
         };
 
-        tokens.pop();
-        parsed_tokens.push(Operator(operator.clone()));
+        parsed_tokens.push(tokens.pop().unwrap());
 
         let mut rhs = parse_try!(parse_primary_expr, tokens, settings, parsed_tokens);
 
-        loop {
-            let binary_rhs = match tokens.last().map(|i| {i.clone()}) {
-                Some(Operator(ref op)) => match settings.op_precedence.get(op).map(|i| {*i}) {
-                    Some(pr) if pr > precedence => {
-                        parse_try!(parse_binary_expr, tokens, settings, parsed_tokens, pr, &rhs)
-                    },
-                    None => return error("unknown operator found"),
-                    _ => break
+        while let Some(Token { kind: Operator(ref op), span }) = tokens.last().cloned() {
+            let binary_rhs = match settings.op_precedence.get(op).copied() {
+                Some(pr) if pr > precedence => {
+                    parse_try!(parse_binary_expr, tokens, settings, parsed_tokens, pr, &rhs)
                 },
+                None if op == "=" => break,
+                None => return error("unknown operator found", span),
                 _ => break
             };
 
             rhs = binary_rhs;
         }
 
-        result = BinaryExpr(operator, Box::new(result), Box::new(rhs));
+        let span = result.span.to(rhs.span);
+        result = Expression::new(BinaryExpr(operator, Box::new(result), Box::new(rhs)), span);
     }
 
     Good(result, parsed_tokens)
 }
 
-fn parse_conditional_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
-    tokens.pop();
-
-    let mut parsed_tokens = vec![If];
+fn parse_conditional_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
     let cond_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
     expect_token!(
-        [Then, Then, ()] <= tokens, parsed_tokens, "expected then"
+        [Then, ()] <= tokens, parsed_tokens, "expected then"
     );
 
     let then_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
-    expect_token!(
-        [Else, Else, ()] <= tokens, parsed_tokens, "expected else"
+    // `else if` needs nothing special, the nested if is the else branch and an else
+    // that follows goes with the innermost if
+    let else_expr = expect_token!(
+        [Else, Some(Box::new(parse_try!(parse_expr, tokens, settings, parsed_tokens)))]
+        else {None} <= tokens, parsed_tokens
     );
 
-    let else_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
-
-    Good(ConditionalExpr {
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(ConditionalExpr {
         cond_expr: Box::new(cond_expr),
         then_expr: Box::new(then_expr),
-        else_expr: Box::new(else_expr)
-    }, parsed_tokens)
+        else_expr
+    }, span), parsed_tokens)
 }
 
-fn parse_loop_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
-    tokens.pop();
-
-    let mut parsed_tokens = Vec::new();
+fn parse_loop_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings, label: Option<String>)
+-> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
     let var_name = expect_token!(
-        [Ident(name), Ident(name.clone()), name] <= tokens, parsed_tokens, "expected identifier after for"
+        [Ident(name), name] <= tokens, parsed_tokens, "expected identifier after for"
     );
 
     expect_token!(
-        [Operator(op), Operator(op.clone()), {
+        [Operator(op), {
             if op.as_str() != "=" {
-                return error("expected '=' after for")
+                return error("expected '=' after for", parsed_tokens.last().unwrap().span)
             }
         }] <= tokens, parsed_tokens, "expected '=' after for"
     );
@@ -492,73 +747,224 @@ fn parse_loop_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> P
     let start_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
     let step_expr  = expect_token!(
-        [Comma, Comma, parse_try!(parse_expr, tokens, settings, parsed_tokens)]
-        else {LiteralExpr(1.0)} <= tokens, parsed_tokens
+        [Comma, parse_try!(parse_expr, tokens, settings, parsed_tokens)]
+        else {Expression::new(LiteralExpr(IntegerLiteral(1)), start_expr.span)} <= tokens, parsed_tokens
     );
 
     let end_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
 
     expect_token!(
-        [In, In, ()] <= tokens, parsed_tokens, "expected in after for"
+        [In, ()] <= tokens, parsed_tokens, "expected in after for"
     );
 
-    let body_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
+    let body_expr = parse_try!(parse_loop_body, tokens, settings, parsed_tokens, (label.clone(), false), parse_expr);
 
-    Good(LoopExpr{
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(LoopExpr{
+        label,
         var_name,
         start_expr: Box::new(start_expr),
         end_expr: Box::new(end_expr),
         step_expr: Box::new(step_expr),
         body_expr: Box::new(body_expr)
-    }, parsed_tokens)
+    }, span), parsed_tokens)
+}
+
+fn parse_while_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings, label: Option<String>)
+-> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
+
+    let cond_expr = match parsed_tokens[0].kind {
+        While => Some(Box::new(parse_try!(parse_expr, tokens, settings, parsed_tokens))),
+        _ => None
+    };
+
+    match tokens.last() {
+        Some(Token { kind: OpeningBrackets, .. }) => (),
+        Some(token) => return error("expected '{' before the loop body", token.span),
+        None => {
+            parsed_tokens.reverse();
+            tokens.extend(parsed_tokens);
+            return NotComplete;
+        }
+    }
+
+    let loop_value = cond_expr.is_none();
+    let body_expr = parse_try!(parse_loop_body, tokens, settings, parsed_tokens, (label.clone(), loop_value), parse_brackets_expr);
+
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(WhileExpr {
+        label,
+        cond_expr,
+        body_expr: Box::new(body_expr)
+    }, span), parsed_tokens)
 }
 
-fn parse_let_expr(tokens: &mut Vec<Tokens>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
-    tokens.pop();
-    let mut parsed_tokens = vec![Let];
+/// Parses the body of a loop with `parse_body`, where `break` and `continue` can refer
+/// to the loop.
+fn parse_loop_body(tokens: &mut Vec<Token>, settings: &mut ParserSettings, target: (Option<String>, bool),
+                   parse_body: fn(&mut Vec<Token>, &mut ParserSettings) -> PartParsingResult<Expression>)
+-> PartParsingResult<Expression> {
+    settings.loops.push(target);
+    let body = parse_body(tokens, settings);
+    settings.loops.pop();
+    body
+}
+
+/// A loop with a label in front, like `'dis: while c { ... }`.
+fn parse_labeled_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = Vec::new();
+
+    let label = expect_token!(
+        [Label(name), name] <= tokens, parsed_tokens, "label expected"
+    );
+    expect_token!(
+        [Colon, ()] <= tokens, parsed_tokens, "expected ':' after label"
+    );
+
+    let expr = match tokens.last() {
+        Some(Token { kind: While, .. }) | Some(Token { kind: Loop, .. }) => {
+            parse_try!(parse_while_expr, tokens, settings, parsed_tokens, Some(label))
+        },
+        Some(Token { kind: For, .. }) => parse_try!(parse_loop_expr, tokens, settings, parsed_tokens, Some(label)),
+        Some(token) => return error("expected a loop after the label", token.span),
+        None => {
+            parsed_tokens.reverse();
+            tokens.extend(parsed_tokens);
+            return NotComplete;
+        }
+    };
+
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(expr.kind, span), parsed_tokens)
+}
+
+/// Checks that `break` or `continue` with `label` is inside a loop it can refer to, and
+/// tells whether that loop can be left with a value.
+fn loop_target(settings: &ParserSettings, keyword: &str, label: &Option<String>) -> Result<bool, String> {
+    let target = match *label {
+        Some(ref name) => settings.loops.iter().rev().find(|target| target.0.as_ref() == Some(name)),
+        None => settings.loops.last()
+    };
+
+    match (target, label) {
+        (Some(target), _) => Ok(target.1),
+        (None, Some(name)) => Err(format!("use of undeclared label '{}", name)),
+        (None, None) => Err(format!("{} outside of a loop", keyword))
+    }
+}
+
+/// Whether an expression can start with `kind`, which decides if `break` is followed
+/// by a value.
+fn starts_expression(kind: &Tokens) -> bool {
+    matches!(*kind,
+        Ident(_) | Integer(_) | Number(_) | Bool(_) | Operator(_) | OpeningParenthesis | OpeningBrackets |
+        If | For | While | Loop | Break | Continue | Return | Let | Label(_))
+}
+
+fn parse_break_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
+
+    let label = expect_token!(
+        [Label(name), Some(name)] else {None} <= tokens, parsed_tokens
+    );
+    let takes_value = match loop_target(settings, "break", &label) {
+        Ok(takes_value) => takes_value,
+        Err(message) => return error(&message, span_of(&parsed_tokens))
+    };
+
+    let value = match tokens.last() {
+        Some(token) if starts_expression(&token.kind) => {
+            let value = parse_try!(parse_expr, tokens, settings, parsed_tokens);
+            if !takes_value {
+                return error("only loop can be left with a value, not while or for", value.span);
+            }
+            Some(Box::new(value))
+        },
+        _ => None
+    };
+
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(BreakExpr { label, value }, span), parsed_tokens)
+}
+
+fn parse_continue_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
+
+    let label = expect_token!(
+        [Label(name), Some(name)] else {None} <= tokens, parsed_tokens
+    );
+    if let Err(message) = loop_target(settings, "continue", &label) {
+        return error(&message, span_of(&parsed_tokens));
+    }
+
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(ContinueExpr(label), span), parsed_tokens)
+}
+
+fn parse_return_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
+
+    let value = parse_try!(parse_expr, tokens, settings, parsed_tokens);
+
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(ReturnExpr(Box::new(value)), span), parsed_tokens)
+}
+
+fn parse_let_expr(tokens: &mut Vec<Token>, settings: &mut ParserSettings) -> PartParsingResult<Expression> {
+    let mut parsed_tokens = vec![tokens.pop().unwrap()];
     let mut vars = Vec::new();
 
     loop {
+        let mutable = expect_token!(
+            [Mut, true] else {false} <= tokens, parsed_tokens
+        );
+
         let var_name = expect_token!(
-            [Ident(name), Ident(name.clone()), name] <= tokens, parsed_tokens, "expected identifier list after let"
+            [Ident(name), name] <= tokens, parsed_tokens, "expected identifier list after let"
         );
+        let name_span = parsed_tokens.last().unwrap().span;
+
+        let ty = parse_try!(parse_type_annotation, tokens, settings, parsed_tokens, Colon);
 
         let init_expr = expect_token!(
-            [Operator(op), Operator(op.clone()), {
+            [Operator(op), {
                 if op.as_str() != "=" {
-                    return error("expected '=' in variable initialization")
+                    return error("expected '=' in variable initialization", parsed_tokens.last().unwrap().span)
                 }
                 parse_try!(parse_expr, tokens, settings, parsed_tokens)
             }]
-            else {LiteralExpr(1.0)} <= tokens, parsed_tokens
+            else {Expression::new(LiteralExpr(IntegerLiteral(1)), name_span)} <= tokens, parsed_tokens
         );
 
-        vars.push((var_name, init_expr));
+        vars.push((var_name, ty, init_expr, mutable));
 
         expect_token!(
-            [Comma, Comma, ()] else {break} <= tokens, parsed_tokens
+            [Comma, ()] else {break} <= tokens, parsed_tokens
         );
     }
 
     expect_token!(
-        [In, In, ()] <= tokens,parsed_tokens, "expected 'in' after var"
+        [In, ()] <= tokens,parsed_tokens, "expected 'in' after var"
     );
 
     let body_expr = parse_try!(parse_expr, tokens, settings, parsed_tokens);
-    Good(VarExpr {
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(VarExpr {
         vars,
         body_expr: Box::new(body_expr)
-    }, parsed_tokens)
+    }, span), parsed_tokens)
 }
 
-fn parse_unary_expr(tokens : &mut Vec<Tokens>, settings : &mut ParserSettings) -> PartParsingResult<Expression> {
+fn parse_unary_expr(tokens : &mut Vec<Token>, settings : &mut ParserSettings) -> PartParsingResult<Expression> {
     let mut parsed_tokens = Vec::new();
 
     let name = expect_token!(
-        [Operator(name), Operator(name.clone()), name] <= tokens,
+        [Operator(name), name] <= tokens,
         parsed_tokens, "unary operator expected");
 
     let operand = parse_try!(parse_primary_expr, tokens, settings, parsed_tokens);
 
-    Good(UnaryExpr(name, Box::new(operand)), parsed_tokens)
+    let span = span_of(&parsed_tokens);
+    Good(Expression::new(UnaryExpr(name, Box::new(operand)), span), parsed_tokens)
 }
\ No newline at end of file
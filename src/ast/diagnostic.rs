@@ -0,0 +1,51 @@
+use std::fmt;
+use crate::lexer::error::LexError;
+use crate::lexer::span::Span;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span
+}
+
+impl Diagnostic {
+    pub fn error(message: &str, span: Span) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message: message.to_string(), span }
+    }
+
+    pub fn warning(message: &str, span: Span) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message: message.to_string(), span }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl From<LexError> for Diagnostic {
+    fn from(error: LexError) -> Diagnostic {
+        Diagnostic::error(&error.kind.to_string(), error.span)
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning")
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.span, self.severity, self.message)
+    }
+}
\ No newline at end of file
@@ -1 +1,4 @@
-pub mod ast;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod ast;
+pub mod diagnostic;
+pub mod types;
\ No newline at end of file
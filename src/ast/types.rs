@@ -0,0 +1,93 @@
+use std::fmt;
+
+pub use self::Type::{
+    Bool,
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+    Unit
+};
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Type {
+    Bool,
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+    /// `()`, the type of an `if` without `else`, which has no value.
+    Unit
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "bool" => Some(Bool),
+            "i32" => Some(I32),
+            "i64" => Some(I64),
+            "u32" => Some(U32),
+            "u64" => Some(U64),
+            "f32" => Some(F32),
+            "f64" => Some(F64),
+            _ => None
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(*self, I32 | I64 | U32 | U64)
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(*self, I32 | I64)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(*self, F32 | F64)
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        self.is_integer() || self.is_float()
+    }
+
+    /// Smallest and largest value of an integer type.
+    pub fn integer_range(&self) -> Option<(i128, i128)> {
+        match *self {
+            I32 => Some((i32::MIN as i128, i32::MAX as i128)),
+            I64 => Some((i64::MIN as i128, i64::MAX as i128)),
+            U32 => Some((0, u32::MAX as i128)),
+            U64 => Some((0, u64::MAX as i128)),
+            _ => None
+        }
+    }
+
+    /// Width of the machine representation.
+    pub fn bits(&self) -> u32 {
+        match *self {
+            Bool => 1,
+            I32 | U32 | F32 => 32,
+            I64 | U64 | F64 => 64,
+            Unit => 0
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Bool => "bool",
+            I32 => "i32",
+            I64 => "i64",
+            U32 => "u32",
+            U64 => "u64",
+            F32 => "f32",
+            F64 => "f64",
+            Unit => "()"
+        };
+        write!(f, "{}", name)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,965 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::rc::Rc;
+use libc::{c_char, c_uint};
+use llvm_sys::prelude::*;
+use llvm_sys::core::*;
+use llvm_sys::analysis::{LLVMVerifyFunction, LLVMVerifierFailureAction};
+use llvm_sys::{LLVMAttributeIndex, LLVMAttributeReturnIndex, LLVMIntPredicate, LLVMRealPredicate};
+
+use crate::ast::ast::{
+    ASTNode,
+    Expression,
+    Functions,
+    Prototype,
+    ExternNode,
+    FunctionsNode,
+    LiteralExpr,
+    VariableExpr,
+    UnaryExpr,
+    BinaryExpr,
+    ConditionalExpr,
+    LoopExpr,
+    WhileExpr,
+    BreakExpr,
+    ContinueExpr,
+    ReturnExpr,
+    VarExpr,
+    AssignExpr,
+    BlockExpr,
+    CallExpr,
+    Literal,
+    IntegerLiteral,
+    FloatLiteral,
+    BoolLiteral,
+};
+use crate::codegen::module::{ContextHandle, Module};
+use crate::typeck::checker::{COMPARISON_OPERATORS, LOGICAL_OPERATORS};
+use crate::ast::types::{Type, Bool, I32, I64, U32, U64, F32, F64, Unit};
+
+pub const ANONYMOUS_FUNCTION_NAME: &str = "__anon_expr";
+
+pub type IRBuildingResult = Result<LLVMValueRef, String>;
+
+pub trait IRBuilder {
+    fn codegen(&self, context: &mut Context, module: &Module) -> IRBuildingResult;
+}
+
+fn c_str(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+/// What a variable name stands for in the function being generated.
+#[derive(Clone, Copy)]
+enum Binding {
+    /// The value of an immutable variable.
+    Value(LLVMValueRef),
+    /// The stack slot of a `mut` variable. Slots are allocated in the entry block, where
+    /// `mem2reg`, part of every optimizing pipeline, turns them back into registers.
+    Slot(LLVMValueRef)
+}
+
+/// Where `break` and `continue` jump to for one of the loops around the code being
+/// generated.
+struct LoopTarget {
+    label: Option<String>,
+    continue_block: LLVMBasicBlockRef,
+    break_block: LLVMBasicBlockRef,
+    /// Type of the loop, `break` without a value leaves it with zero.
+    ty: Type,
+    /// Values `break` left the loop with and the blocks they came from.
+    break_values: Vec<(LLVMValueRef, LLVMBasicBlockRef)>
+}
+
+/// Holds the LLVM context, the instruction builder and the symbol table used while
+/// lowering the AST. Modules created by `new_module` share the LLVM context, which
+/// lives until the last of them is gone.
+///
+/// Every prototype generated through the context is remembered, which lets a module
+/// call functions that were defined in an earlier module.
+pub struct Context {
+    context: Rc<ContextHandle>,
+    builder: LLVMBuilderRef,
+    named_values: HashMap<String, Binding>,
+    loops: Vec<LoopTarget>,
+    /// Block `return` jumps to in the function being generated, made by the first one.
+    return_block: Option<LLVMBasicBlockRef>,
+    /// Values the function returns and the blocks they come from.
+    return_values: Vec<(LLVMValueRef, LLVMBasicBlockRef)>,
+    prototypes: HashMap<String, Prototype>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context::with_handle(ContextHandle::new())
+    }
+
+    /// Generates code into an existing LLVM context, like the one of an `OrcEngine`.
+    pub fn with_handle(context: Rc<ContextHandle>) -> Context {
+        unsafe {
+            let builder = LLVMCreateBuilderInContext(context.to_ref());
+
+            Context {
+                context,
+                builder,
+                named_values: HashMap::new(),
+                loops: Vec::new(),
+                return_block: None,
+                return_values: Vec::new(),
+                prototypes: HashMap::new(),
+            }
+        }
+    }
+
+    pub fn new_module(&self, name: &str) -> Module {
+        Module::new(name, &self.context)
+    }
+
+    pub fn to_ref(&self) -> LLVMContextRef {
+        self.context.to_ref()
+    }
+
+    /// Looks `name` up in `module`, declaring it there if it was generated in another one.
+    fn get_or_declare_function(&mut self, module: &Module, name: &str) -> Option<LLVMValueRef> {
+        if let Some(function) = get_function(module.to_ref(), name) {
+            return Some(function);
+        }
+
+        let prototype = self.prototypes.get(name)?.clone();
+        prototype.codegen(self, module).ok()
+    }
+
+    /// The prototype calls to `name` are generated with.
+    pub fn prototype(&self, name: &str) -> Option<&Prototype> {
+        self.prototypes.get(name)
+    }
+
+    /// Undoes the registration of a prototype whose function failed to generate or was
+    /// rejected by the JIT.
+    pub fn forget_prototype(&mut self, name: &str, previous: Option<Prototype>) {
+        match previous {
+            Some(prototype) => {self.prototypes.insert(name.to_string(), prototype);},
+            None => {self.prototypes.remove(name);}
+        };
+    }
+
+    fn llvm_type(&self, ty: Type) -> LLVMTypeRef {
+        unsafe {
+            match ty {
+                Bool => LLVMInt1TypeInContext(self.context.to_ref()),
+                I32 | U32 => LLVMInt32TypeInContext(self.context.to_ref()),
+                I64 | U64 => LLVMInt64TypeInContext(self.context.to_ref()),
+                F32 => LLVMFloatTypeInContext(self.context.to_ref()),
+                F64 => LLVMDoubleTypeInContext(self.context.to_ref()),
+                // an empty struct, which C returns like void
+                Unit => LLVMStructTypeInContext(self.context.to_ref(), std::ptr::null_mut(), 0, 0)
+            }
+        }
+    }
+
+    fn const_literal(&self, literal: &Literal, ty: Type) -> LLVMValueRef {
+        let llvm_type = self.llvm_type(ty);
+        unsafe {
+            match (literal, ty.is_float()) {
+                (&IntegerLiteral(value), true) => LLVMConstReal(llvm_type, value as f64),
+                (&IntegerLiteral(value), false) => LLVMConstInt(llvm_type, value as u64, ty.is_signed() as LLVMBool),
+                (&FloatLiteral(value), _) => LLVMConstReal(llvm_type, value),
+                (&BoolLiteral(value), _) => LLVMConstInt(llvm_type, value as u64, 0)
+            }
+        }
+    }
+
+    fn const_zero(&self, ty: Type) -> LLVMValueRef {
+        unsafe {
+            LLVMConstNull(self.llvm_type(ty))
+        }
+    }
+
+    fn append_block(&self, function: LLVMValueRef, name: &str) -> LLVMBasicBlockRef {
+        let name = c_str(name);
+        unsafe {
+            LLVMAppendBasicBlockInContext(self.context.to_ref(), function, name.as_ptr())
+        }
+    }
+
+    fn current_function(&self) -> LLVMValueRef {
+        unsafe {
+            LLVMGetBasicBlockParent(LLVMGetInsertBlock(self.builder))
+        }
+    }
+
+    /// Allocates a stack slot for a value of type `ty` at the start of the entry block
+    /// of the current function.
+    fn build_entry_alloca(&self, ty: Type, name: &str) -> LLVMValueRef {
+        let name = c_str(name);
+        unsafe {
+            let entry = LLVMGetEntryBasicBlock(self.current_function());
+            let builder = LLVMCreateBuilderInContext(self.to_ref());
+            match LLVMGetFirstInstruction(entry) {
+                first if first.is_null() => LLVMPositionBuilderAtEnd(builder, entry),
+                first => LLVMPositionBuilderBefore(builder, first)
+            }
+
+            let slot = LLVMBuildAlloca(builder, self.llvm_type(ty), name.as_ptr());
+            LLVMDisposeBuilder(builder);
+            slot
+        }
+    }
+
+    /// The loop `break` or `continue` with `label` jumps out of or back to.
+    fn loop_target(&self, label: &Option<String>) -> Option<usize> {
+        match *label {
+            Some(ref name) => self.loops.iter().rposition(|target| target.label.as_ref() == Some(name)),
+            None => self.loops.len().checked_sub(1)
+        }
+    }
+
+    /// Continues in a new block after a jump. Nothing reaches the code that follows, the
+    /// jump itself gives an undefined value of type `ty`.
+    fn build_unreachable_continuation(&self, ty: Type) -> LLVMValueRef {
+        let block = self.append_block(self.current_function(), "afterjump");
+        unsafe {
+            LLVMPositionBuilderAtEnd(self.builder, block);
+            LLVMGetUndef(self.llvm_type(ty))
+        }
+    }
+
+    fn build_call(&self, function: LLVMValueRef, args: &mut [LLVMValueRef], name: &str) -> LLVMValueRef {
+        let name = c_str(name);
+        unsafe {
+            LLVMBuildCall2(
+                self.builder,
+                LLVMGlobalGetValueType(function),
+                function,
+                args.as_mut_ptr(),
+                args.len() as c_uint,
+                name.as_ptr(),
+            )
+        }
+    }
+
+    fn add_zero_extension(&self, function: LLVMValueRef, index: LLVMAttributeIndex) {
+        unsafe {
+            let kind = LLVMGetEnumAttributeKindForName(c"zeroext".as_ptr(), "zeroext".len());
+            let attribute = LLVMCreateEnumAttribute(self.to_ref(), kind, 0);
+            LLVMAddAttributeAtIndex(function, index, attribute);
+        }
+    }
+
+    /// Turns a condition into an `i1`, numbers are true when they are not zero.
+    fn build_truthiness(&self, value: LLVMValueRef, ty: Type, name: &str) -> LLVMValueRef {
+        let name = c_str(name);
+        unsafe {
+            match ty {
+                Bool => value,
+                ty if ty.is_float() => LLVMBuildFCmp(
+                    self.builder,
+                    LLVMRealPredicate::LLVMRealONE,
+                    value,
+                    self.const_zero(ty),
+                    name.as_ptr(),
+                ),
+                ty => LLVMBuildICmp(
+                    self.builder,
+                    LLVMIntPredicate::LLVMIntNE,
+                    value,
+                    self.const_zero(ty),
+                    name.as_ptr(),
+                )
+            }
+        }
+    }
+
+    /// Takes a shift amount modulo the width of `ty`, shifting by the full width or more
+    /// would give poison.
+    fn build_shift_amount(&self, amount: LLVMValueRef, ty: Type) -> LLVMValueRef {
+        unsafe {
+            let mask = LLVMConstInt(self.llvm_type(ty), (ty.bits() - 1) as u64, 0);
+            LLVMBuildAnd(self.builder, amount, mask, c_str("shamt").as_ptr())
+        }
+    }
+
+    /// Compares two values of type `ty` with one of the `COMPARISON_OPERATORS`. Ordering
+    /// floats is unordered, true when either side is NaN, while `==` is ordered so that
+    /// NaN is not equal to itself.
+    fn build_comparison(&self, operator: &str, lhs: LLVMValueRef, rhs: LLVMValueRef, ty: Type) -> LLVMValueRef {
+        let name = c_str("cmptmp");
+        unsafe {
+            if ty.is_float() {
+                let predicate = match operator {
+                    "<" => LLVMRealPredicate::LLVMRealULT,
+                    "<=" => LLVMRealPredicate::LLVMRealULE,
+                    ">" => LLVMRealPredicate::LLVMRealUGT,
+                    ">=" => LLVMRealPredicate::LLVMRealUGE,
+                    "==" => LLVMRealPredicate::LLVMRealOEQ,
+                    _ => LLVMRealPredicate::LLVMRealUNE
+                };
+                LLVMBuildFCmp(self.builder, predicate, lhs, rhs, name.as_ptr())
+            } else {
+                let signed = ty.is_signed();
+                let predicate = match operator {
+                    "<" if signed => LLVMIntPredicate::LLVMIntSLT,
+                    "<" => LLVMIntPredicate::LLVMIntULT,
+                    "<=" if signed => LLVMIntPredicate::LLVMIntSLE,
+                    "<=" => LLVMIntPredicate::LLVMIntULE,
+                    ">" if signed => LLVMIntPredicate::LLVMIntSGT,
+                    ">" => LLVMIntPredicate::LLVMIntUGT,
+                    ">=" if signed => LLVMIntPredicate::LLVMIntSGE,
+                    ">=" => LLVMIntPredicate::LLVMIntUGE,
+                    "==" => LLVMIntPredicate::LLVMIntEQ,
+                    _ => LLVMIntPredicate::LLVMIntNE
+                };
+                LLVMBuildICmp(self.builder, predicate, lhs, rhs, name.as_ptr())
+            }
+        }
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeBuilder(self.builder);
+        }
+    }
+}
+
+/// Type of a checked expression, unchecked ones are taken to be `f64`.
+fn type_of(expr: &Expression) -> Type {
+    expr.ty.unwrap_or(F64)
+}
+
+fn get_function(module: LLVMModuleRef, name: &str) -> Option<LLVMValueRef> {
+    let name = c_str(name);
+    let function = unsafe { LLVMGetNamedFunction(module, name.as_ptr()) };
+    if function.is_null() {
+        None
+    } else {
+        Some(function)
+    }
+}
+
+/// Lowers a whole program into a fresh module named `name`.
+pub fn build_module(context: &mut Context, name: &str, ast: &[ASTNode]) -> Result<Module, String> {
+    let module = context.new_module(name);
+
+    for node in ast {
+        node.codegen(context, &module)?;
+    }
+
+    Ok(module)
+}
+
+pub const ENTRY_POINT_NAME: &str = "main";
+
+/// Makes the Tobichi function `main` the entry point of an executable. Unless it already
+/// is `() -> i32` it is renamed and called from a C `main` that turns its result into the
+/// exit status.
+pub fn build_entry_point(context: &mut Context, module: &Module) -> Result<(), String> {
+    let module = module.to_ref();
+    let prototype = match context.prototypes.get(ENTRY_POINT_NAME) {
+        Some(prototype) if get_function(module, ENTRY_POINT_NAME).is_some() => prototype.clone(),
+        _ => return Err("no main function to use as the entry point".to_string())
+    };
+
+    if !prototype.args.is_empty() {
+        return Err(format!("{}: main cannot take arguments", prototype.span));
+    }
+
+    let ret = prototype.ret_type.unwrap_or(F64);
+    if ret == I32 {
+        return Ok(());
+    }
+
+    let main = get_function(module, ENTRY_POINT_NAME).unwrap();
+    let name = "__tobichi_main";
+    unsafe {
+        LLVMSetValueName2(main, name.as_ptr() as *const c_char, name.len());
+
+        let int_type = context.llvm_type(I32);
+        let fn_type = LLVMFunctionType(int_type, std::ptr::null_mut(), 0, 0);
+        let entry_point = LLVMAddFunction(module, c_str(ENTRY_POINT_NAME).as_ptr(), fn_type);
+        let block = context.append_block(entry_point, "entry");
+        LLVMPositionBuilderAtEnd(context.builder, block);
+
+        let value = context.build_call(main, &mut [], "result");
+        let status = match ret {
+            Unit => LLVMConstInt(int_type, 0, 0),
+            Bool => LLVMBuildZExt(context.builder, value, int_type, c_str("status").as_ptr()),
+            ty if ty.is_float() => LLVMBuildFPToSI(context.builder, value, int_type, c_str("status").as_ptr()),
+            ty => LLVMBuildIntCast2(context.builder, value, int_type, ty.is_signed() as LLVMBool, c_str("status").as_ptr())
+        };
+        LLVMBuildRet(context.builder, status);
+    }
+
+    Ok(())
+}
+
+impl IRBuilder for Vec<ASTNode> {
+    fn codegen(&self, context: &mut Context, module: &Module) -> IRBuildingResult {
+        let mut result = Err("empty AST".to_string());
+        for node in self.iter() {
+            result = Ok(node.codegen(context, module)?);
+        }
+
+        result
+    }
+}
+
+impl IRBuilder for ASTNode {
+    fn codegen(&self, context: &mut Context, module: &Module) -> IRBuildingResult {
+        match *self {
+            ExternNode(ref prototype) => prototype.codegen(context, module),
+            FunctionsNode(ref function) => function.codegen(context, module)
+        }
+    }
+}
+
+impl IRBuilder for Prototype {
+    fn codegen(&self, context: &mut Context, module: &Module) -> IRBuildingResult {
+        let anonymous = self.name.is_empty();
+        let name = if anonymous { ANONYMOUS_FUNCTION_NAME } else { self.name.as_str() };
+
+        if !anonymous {
+            if let Some(function) = get_function(module.to_ref(), name) {
+                unsafe {
+                    if LLVMCountBasicBlocks(function) != 0 {
+                        return Err(format!("{}: redefinition of function {}", self.span, name));
+                    }
+
+                    if LLVMCountParams(function) as usize != self.args.len() {
+                        return Err(format!("{}: redefinition of function {} with different number of args", self.span, name));
+                    }
+                }
+
+                return Ok(function);
+            }
+        }
+
+        // anonymous functions are always added, LLVM makes their names unique
+        let mut param_types: Vec<_> = self.args.iter()
+            .map(|arg| context.llvm_type(arg.1.unwrap_or(F64)))
+            .collect();
+        let function = unsafe {
+            let fn_type = LLVMFunctionType(
+                context.llvm_type(self.ret_type.unwrap_or(F64)),
+                param_types.as_mut_ptr(),
+                param_types.len() as c_uint,
+                0,
+            );
+            let name = c_str(name);
+            LLVMAddFunction(module.to_ref(), name.as_ptr(), fn_type)
+        };
+
+        // bools are passed zero extended like C's _Bool, so callers can read a whole byte
+        if self.ret_type == Some(Bool) {
+            context.add_zero_extension(function, LLVMAttributeReturnIndex);
+        }
+        for (i, arg) in self.args.iter().enumerate() {
+            if arg.1 == Some(Bool) {
+                context.add_zero_extension(function, i as c_uint + 1);
+            }
+        }
+
+        for (i, (arg, _)) in self.args.iter().enumerate() {
+            unsafe {
+                let param = LLVMGetParam(function, i as c_uint);
+                LLVMSetValueName2(param, arg.as_ptr() as *const c_char, arg.len());
+            }
+        }
+
+        if !anonymous {
+            context.prototypes.insert(self.name.clone(), self.clone());
+        }
+
+        Ok(function)
+    }
+}
+
+impl IRBuilder for Functions {
+    fn codegen(&self, context: &mut Context, module: &Module) -> IRBuildingResult {
+        context.named_values.clear();
+        context.loops.clear();
+        context.return_block = None;
+        context.return_values.clear();
+
+        let previous = context.prototypes.get(&self.prototype.name).cloned();
+        let function = self.prototype.codegen(context, module)?;
+
+        let entry = context.append_block(function, "entry");
+        unsafe {
+            LLVMPositionBuilderAtEnd(context.builder, entry);
+        }
+
+        for (i, (arg, _)) in self.prototype.args.iter().enumerate() {
+            let param = unsafe { LLVMGetParam(function, i as c_uint) };
+            context.named_values.insert(arg.clone(), Binding::Value(param));
+        }
+
+        let body = match self.body.codegen(context, module) {
+            Ok(value) => value,
+            Err(message) => {
+                unsafe {
+                    LLVMDeleteFunction(function);
+                }
+                context.forget_prototype(&self.prototype.name, previous);
+                return Err(message);
+            }
+        };
+
+        unsafe {
+            match context.return_block {
+                Some(block) => {
+                    // every return and the end of the body meet in one block at the end
+                    context.return_values.push((body, LLVMGetInsertBlock(context.builder)));
+                    LLVMBuildBr(context.builder, block);
+                    LLVMMoveBasicBlockAfter(block, LLVMGetLastBasicBlock(function));
+                    LLVMPositionBuilderAtEnd(context.builder, block);
+
+                    let ty = context.llvm_type(self.prototype.ret_type.unwrap_or(F64));
+                    let phi = LLVMBuildPhi(context.builder, ty, c_str("rettmp").as_ptr());
+                    let (mut values, mut blocks): (Vec<_>, Vec<_>) = context.return_values.iter().cloned().unzip();
+                    LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), values.len() as c_uint);
+                    LLVMBuildRet(context.builder, phi);
+                },
+                None => {
+                    LLVMBuildRet(context.builder, body);
+                }
+            }
+
+            if LLVMVerifyFunction(function, LLVMVerifierFailureAction::LLVMReturnStatusAction) != 0 {
+                LLVMDeleteFunction(function);
+                context.forget_prototype(&self.prototype.name, previous);
+                return Err(format!("function verification failed for {}", self.prototype.name));
+            }
+        }
+
+        Ok(function)
+    }
+}
+
+impl IRBuilder for Expression {
+    fn codegen(&self, context: &mut Context, module: &Module) -> IRBuildingResult {
+        match self.kind {
+            LiteralExpr(ref literal) => {
+                Ok(context.const_literal(literal, type_of(self)))
+            },
+
+            VariableExpr(ref name) => {
+                match context.named_values.get(name) {
+                    Some(&Binding::Value(value)) => Ok(value),
+                    Some(&Binding::Slot(slot)) => unsafe {
+                        let ty = context.llvm_type(type_of(self));
+                        Ok(LLVMBuildLoad2(context.builder, ty, slot, c_str(name).as_ptr()))
+                    },
+                    None => Err(format!("{}: unknown variable name {}", self.span, name))
+                }
+            },
+
+            UnaryExpr(ref operator, ref operand) => {
+                let ty = type_of(operand);
+                let operand = operand.codegen(context, module)?;
+
+                let name = "unary".to_string() + operator;
+                match context.get_or_declare_function(module, &name) {
+                    Some(function) => Ok(context.build_call(function, &mut [operand], "unop")),
+                    None => match operator.as_str() {
+                        "-" => unsafe {
+                            let name = c_str("negtmp");
+                            if ty.is_float() {
+                                Ok(LLVMBuildFNeg(context.builder, operand, name.as_ptr()))
+                            } else {
+                                Ok(LLVMBuildNeg(context.builder, operand, name.as_ptr()))
+                            }
+                        },
+                        "~" => unsafe { Ok(LLVMBuildNot(context.builder, operand, c_str("nottmp").as_ptr())) },
+                        "!" => unsafe {
+                            let cond = context.build_truthiness(operand, ty, "notcond");
+                            Ok(LLVMBuildNot(context.builder, cond, c_str("nottmp").as_ptr()))
+                        },
+                        _ => Err(format!("{}: unknown unary operator {}", self.span, operator))
+                    }
+                }
+            },
+
+            // the right side only runs when the left one does not decide the result
+            BinaryExpr(ref operator, ref lhs, ref rhs) if LOGICAL_OPERATORS.contains(&operator.as_str()) => {
+                let lhs_value = lhs.codegen(context, module)?;
+                let lhs_cond = context.build_truthiness(lhs_value, type_of(lhs), "lhscond");
+
+                let function = context.current_function();
+                let rhs_block = context.append_block(function, "logic.rhs");
+                let merge_block = context.append_block(function, "logic.end");
+
+                let lhs_end = unsafe {
+                    let block = LLVMGetInsertBlock(context.builder);
+                    if operator == "&&" {
+                        LLVMBuildCondBr(context.builder, lhs_cond, rhs_block, merge_block);
+                    } else {
+                        LLVMBuildCondBr(context.builder, lhs_cond, merge_block, rhs_block);
+                    }
+                    LLVMPositionBuilderAtEnd(context.builder, rhs_block);
+                    block
+                };
+
+                let rhs_value = rhs.codegen(context, module)?;
+                let rhs_cond = context.build_truthiness(rhs_value, type_of(rhs), "rhscond");
+                unsafe {
+                    LLVMBuildBr(context.builder, merge_block);
+                    let rhs_end = LLVMGetInsertBlock(context.builder);
+                    LLVMPositionBuilderAtEnd(context.builder, merge_block);
+
+                    let bool_type = context.llvm_type(Bool);
+                    let short_circuit = LLVMConstInt(bool_type, (operator == "||") as u64, 0);
+                    let phi = LLVMBuildPhi(context.builder, bool_type, c_str("logictmp").as_ptr());
+                    let mut values = [short_circuit, rhs_cond];
+                    let mut blocks = [lhs_end, rhs_end];
+                    LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+
+                    Ok(phi)
+                }
+            },
+
+            BinaryExpr(ref operator, ref lhs, ref rhs) => {
+                let ty = type_of(lhs);
+                let float = ty.is_float();
+                let lhs = lhs.codegen(context, module)?;
+                let rhs = rhs.codegen(context, module)?;
+
+                unsafe {
+                    match operator.as_str() {
+                        "+" if float => Ok(LLVMBuildFAdd(context.builder, lhs, rhs, c_str("addtmp").as_ptr())),
+                        "+" => Ok(LLVMBuildAdd(context.builder, lhs, rhs, c_str("addtmp").as_ptr())),
+                        "-" if float => Ok(LLVMBuildFSub(context.builder, lhs, rhs, c_str("subtmp").as_ptr())),
+                        "-" => Ok(LLVMBuildSub(context.builder, lhs, rhs, c_str("subtmp").as_ptr())),
+                        "*" if float => Ok(LLVMBuildFMul(context.builder, lhs, rhs, c_str("multmp").as_ptr())),
+                        "*" => Ok(LLVMBuildMul(context.builder, lhs, rhs, c_str("multmp").as_ptr())),
+                        "/" if float => Ok(LLVMBuildFDiv(context.builder, lhs, rhs, c_str("divtmp").as_ptr())),
+                        "/" if ty.is_signed() => Ok(LLVMBuildSDiv(context.builder, lhs, rhs, c_str("divtmp").as_ptr())),
+                        "/" => Ok(LLVMBuildUDiv(context.builder, lhs, rhs, c_str("divtmp").as_ptr())),
+                        "%" if float => Ok(LLVMBuildFRem(context.builder, lhs, rhs, c_str("remtmp").as_ptr())),
+                        "%" if ty.is_signed() => Ok(LLVMBuildSRem(context.builder, lhs, rhs, c_str("remtmp").as_ptr())),
+                        "%" => Ok(LLVMBuildURem(context.builder, lhs, rhs, c_str("remtmp").as_ptr())),
+                        "&" => Ok(LLVMBuildAnd(context.builder, lhs, rhs, c_str("andtmp").as_ptr())),
+                        "|" => Ok(LLVMBuildOr(context.builder, lhs, rhs, c_str("ortmp").as_ptr())),
+                        "^" => Ok(LLVMBuildXor(context.builder, lhs, rhs, c_str("xortmp").as_ptr())),
+                        "<<" => {
+                            let amount = context.build_shift_amount(rhs, ty);
+                            Ok(LLVMBuildShl(context.builder, lhs, amount, c_str("shltmp").as_ptr()))
+                        },
+                        ">>" if ty.is_signed() => {
+                            let amount = context.build_shift_amount(rhs, ty);
+                            Ok(LLVMBuildAShr(context.builder, lhs, amount, c_str("shrtmp").as_ptr()))
+                        },
+                        ">>" => {
+                            let amount = context.build_shift_amount(rhs, ty);
+                            Ok(LLVMBuildLShr(context.builder, lhs, amount, c_str("shrtmp").as_ptr()))
+                        },
+                        op if COMPARISON_OPERATORS.contains(&op) => Ok(context.build_comparison(op, lhs, rhs, ty)),
+                        op => {
+                            let name = "binary".to_string() + op;
+                            match context.get_or_declare_function(module, &name) {
+                                Some(function) => Ok(context.build_call(function, &mut [lhs, rhs], "binop")),
+                                None => Err(format!("{}: unknown binary operator {}", self.span, op))
+                            }
+                        }
+                    }
+                }
+            },
+
+            ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+                let cond_value = cond_expr.codegen(context, module)?;
+                let cond = context.build_truthiness(cond_value, type_of(cond_expr), "ifcond");
+
+                let function = context.current_function();
+                let then_block = context.append_block(function, "then");
+                let else_block = context.append_block(function, "else");
+                let merge_block = context.append_block(function, "ifcont");
+
+                unsafe {
+                    LLVMBuildCondBr(context.builder, cond, then_block, else_block);
+                    LLVMPositionBuilderAtEnd(context.builder, then_block);
+                }
+                let then_value = then_expr.codegen(context, module)?;
+                let then_end = unsafe {
+                    LLVMBuildBr(context.builder, merge_block);
+                    let block = LLVMGetInsertBlock(context.builder);
+                    LLVMPositionBuilderAtEnd(context.builder, else_block);
+                    block
+                };
+
+                let else_value = match *else_expr {
+                    Some(ref else_expr) => else_expr.codegen(context, module)?,
+                    None => context.const_zero(type_of(self))
+                };
+                unsafe {
+                    LLVMBuildBr(context.builder, merge_block);
+                    let else_end = LLVMGetInsertBlock(context.builder);
+                    LLVMPositionBuilderAtEnd(context.builder, merge_block);
+
+                    if type_of(self) == Unit {
+                        return Ok(context.const_zero(Unit));
+                    }
+
+                    let phi = LLVMBuildPhi(context.builder, context.llvm_type(type_of(self)), c_str("iftmp").as_ptr());
+                    let mut values = [then_value, else_value];
+                    let mut blocks = [then_end, else_end];
+                    LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+
+                    Ok(phi)
+                }
+            },
+
+            LoopExpr{ref label, ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+                let ty = type_of(start_expr);
+                let start_value = start_expr.codegen(context, module)?;
+
+                let function = context.current_function();
+                let preheader_block = unsafe { LLVMGetInsertBlock(context.builder) };
+                let loop_block = context.append_block(function, "loop");
+                let step_block = context.append_block(function, "loopstep");
+                let after_block = context.append_block(function, "afterloop");
+
+                let variable = unsafe {
+                    LLVMBuildBr(context.builder, loop_block);
+                    LLVMPositionBuilderAtEnd(context.builder, loop_block);
+
+                    let phi = LLVMBuildPhi(context.builder, context.llvm_type(ty), c_str(var_name).as_ptr());
+                    let mut values = [start_value];
+                    let mut blocks = [preheader_block];
+                    LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 1);
+                    phi
+                };
+
+                let old_value = context.named_values.insert(var_name.clone(), Binding::Value(variable));
+
+                context.loops.push(LoopTarget {
+                    label: label.clone(),
+                    continue_block: step_block,
+                    break_block: after_block,
+                    ty: F64,
+                    break_values: Vec::new()
+                });
+                body_expr.codegen(context, module)?;
+                context.loops.pop();
+
+                unsafe {
+                    LLVMBuildBr(context.builder, step_block);
+                    LLVMPositionBuilderAtEnd(context.builder, step_block);
+                }
+
+                let step_value = step_expr.codegen(context, module)?;
+                let next_value = unsafe {
+                    if ty.is_float() {
+                        LLVMBuildFAdd(context.builder, variable, step_value, c_str("nextvar").as_ptr())
+                    } else {
+                        LLVMBuildAdd(context.builder, variable, step_value, c_str("nextvar").as_ptr())
+                    }
+                };
+
+                let end_value = end_expr.codegen(context, module)?;
+                let end_cond = context.build_truthiness(end_value, type_of(end_expr), "loopcond");
+
+                unsafe {
+                    let loop_end_block = LLVMGetInsertBlock(context.builder);
+                    LLVMBuildCondBr(context.builder, end_cond, loop_block, after_block);
+                    LLVMPositionBuilderAtEnd(context.builder, after_block);
+
+                    let mut values = [next_value];
+                    let mut blocks = [loop_end_block];
+                    LLVMAddIncoming(variable, values.as_mut_ptr(), blocks.as_mut_ptr(), 1);
+                }
+
+                match old_value {
+                    Some(value) => {context.named_values.insert(var_name.clone(), value);},
+                    None => {context.named_values.remove(var_name);}
+                };
+
+                Ok(context.const_zero(F64))
+            },
+
+            WhileExpr{ref label, ref cond_expr, ref body_expr} => {
+                let ty = type_of(self);
+                let function = context.current_function();
+                let body_block = context.append_block(function, "loopbody");
+                let after_block = context.append_block(function, "afterloop");
+
+                let continue_block = match *cond_expr {
+                    Some(ref cond_expr) => {
+                        let cond_block = context.append_block(function, "loopcond");
+                        unsafe {
+                            LLVMBuildBr(context.builder, cond_block);
+                            LLVMPositionBuilderAtEnd(context.builder, cond_block);
+                        }
+
+                        let cond_value = cond_expr.codegen(context, module)?;
+                        let cond = context.build_truthiness(cond_value, type_of(cond_expr), "loopcond");
+                        unsafe {
+                            LLVMBuildCondBr(context.builder, cond, body_block, after_block);
+                        }
+                        cond_block
+                    },
+                    None => {
+                        unsafe {
+                            LLVMBuildBr(context.builder, body_block);
+                        }
+                        body_block
+                    }
+                };
+
+                unsafe {
+                    LLVMPositionBuilderAtEnd(context.builder, body_block);
+                }
+                context.loops.push(LoopTarget {
+                    label: label.clone(),
+                    continue_block,
+                    break_block: after_block,
+                    ty,
+                    break_values: Vec::new()
+                });
+                body_expr.codegen(context, module)?;
+                let target = context.loops.pop().unwrap();
+
+                unsafe {
+                    LLVMBuildBr(context.builder, continue_block);
+                    LLVMPositionBuilderAtEnd(context.builder, after_block);
+                }
+
+                // a while loop, or a loop that is never left, has no value to merge
+                if cond_expr.is_some() || target.break_values.is_empty() {
+                    return Ok(context.const_zero(ty));
+                }
+
+                unsafe {
+                    let phi = LLVMBuildPhi(context.builder, context.llvm_type(ty), c_str("looptmp").as_ptr());
+                    let (mut values, mut blocks): (Vec<_>, Vec<_>) = target.break_values.into_iter().unzip();
+                    LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), values.len() as c_uint);
+                    Ok(phi)
+                }
+            },
+
+            BreakExpr{ref label, ref value} => {
+                let target = match context.loop_target(label) {
+                    Some(target) => target,
+                    None => return Err(format!("{}: break outside of a loop", self.span))
+                };
+
+                let value = match *value {
+                    Some(ref value) => value.codegen(context, module)?,
+                    None => context.const_zero(context.loops[target].ty)
+                };
+                unsafe {
+                    let block = LLVMGetInsertBlock(context.builder);
+                    context.loops[target].break_values.push((value, block));
+                    LLVMBuildBr(context.builder, context.loops[target].break_block);
+                }
+
+                Ok(context.build_unreachable_continuation(type_of(self)))
+            },
+
+            ContinueExpr(ref label) => {
+                let target = match context.loop_target(label) {
+                    Some(target) => target,
+                    None => return Err(format!("{}: continue outside of a loop", self.span))
+                };
+
+                unsafe {
+                    LLVMBuildBr(context.builder, context.loops[target].continue_block);
+                }
+                Ok(context.build_unreachable_continuation(type_of(self)))
+            },
+
+            ReturnExpr(ref value) => {
+                let value = value.codegen(context, module)?;
+                let block = match context.return_block {
+                    Some(block) => block,
+                    None => {
+                        let block = context.append_block(context.current_function(), "return");
+                        context.return_block = Some(block);
+                        block
+                    }
+                };
+
+                unsafe {
+                    context.return_values.push((value, LLVMGetInsertBlock(context.builder)));
+                    LLVMBuildBr(context.builder, block);
+                }
+                Ok(context.build_unreachable_continuation(type_of(self)))
+            },
+
+            VarExpr{ref vars, ref body_expr} => {
+                let mut old_bindings = Vec::new();
+
+                for (name, _, init_expr, mutable) in vars.iter() {
+                    let init_value = init_expr.codegen(context, module)?;
+                    let binding = if *mutable {
+                        let slot = context.build_entry_alloca(type_of(init_expr), name);
+                        unsafe {
+                            LLVMBuildStore(context.builder, init_value, slot);
+                        }
+                        Binding::Slot(slot)
+                    } else {
+                        Binding::Value(init_value)
+                    };
+                    old_bindings.push((name.clone(), context.named_values.insert(name.clone(), binding)));
+                }
+
+                let body_value = body_expr.codegen(context, module)?;
+
+                for (name, old_value) in old_bindings.into_iter().rev() {
+                    match old_value {
+                        Some(value) => {context.named_values.insert(name, value);},
+                        None => {context.named_values.remove(&name);}
+                    };
+                }
+
+                Ok(body_value)
+            },
+
+            AssignExpr(ref name, ref value) => {
+                let slot = match context.named_values.get(name) {
+                    Some(&Binding::Slot(slot)) => slot,
+                    Some(_) => return Err(format!("{}: cannot assign to immutable variable {}", self.span, name)),
+                    None => return Err(format!("{}: unknown variable name {}", self.span, name))
+                };
+
+                let value = value.codegen(context, module)?;
+                unsafe {
+                    LLVMBuildStore(context.builder, value, slot);
+                }
+                Ok(value)
+            },
+
+            BlockExpr(ref exprs) => {
+                let mut value = context.const_zero(F64);
+                for expr in exprs.iter() {
+                    value = expr.codegen(context, module)?;
+                }
+                Ok(value)
+            },
+
+            CallExpr(ref name, ref args) => {
+                let function = match context.get_or_declare_function(module, name) {
+                    Some(function) => function,
+                    None => return Err(format!("{}: unknown function referenced {}", self.span, name))
+                };
+
+                if unsafe { LLVMCountParams(function) } as usize != args.len() {
+                    return Err(format!("{}: incorrect number of arguments passed to {}", self.span, name));
+                }
+
+                let mut arg_values = Vec::new();
+                for arg in args.iter() {
+                    arg_values.push(arg.codegen(context, module)?);
+                }
+
+                Ok(context.build_call(function, &mut arg_values, "calltmp"))
+            }
+        }
+    }
+}
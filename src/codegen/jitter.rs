@@ -1,89 +1,414 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::CString;
-use libc::{c_char, c_uint, size_t};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use libc::{c_char, c_uint, c_void, size_t};
 use llvm_sys::prelude::*;
 use llvm_sys::core::*;
+use llvm_sys::{LLVMAttributeIndex, LLVMAttributeReturnIndex, LLVMTypeKind};
 use llvm_sys::execution_engine::*;
+use llvm_sys::target::*;
 use llvm_sys::target_machine::*;
 use llvm_sys::execution_engine::LLVMMCJITCompilerOptions;
 
-pub struct FrozenModule {
+use crate::codegen::module::{take_message, ContextHandle, Module};
+use crate::codegen::value::GenericValue;
+
+/// A module owned by an `ExecutionEngine`. It cannot outlive the engine and can only
+/// be turned back into a `Module` by removing it from that engine.
+pub struct FrozenModule<'ee> {
     module: LLVMModuleRef,
+    ee: LLVMExecutionEngineRef,
+    _engine: PhantomData<&'ee ExecutionEngine>,
 }
 
-impl FrozenModule {
-    pub fn get(&self) -> LLVMModuleRef {
+impl FrozenModule<'_> {
+    /// The raw module.
+    ///
+    /// # Safety
+    ///
+    /// The engine owns the module, it must not be disposed or changed through the result.
+    pub unsafe fn get(&self) -> LLVMModuleRef {
         self.module
     }
 }
 
-pub struct ExecutionEngine {
-    ee: LLVMExecutionEngineRef,
+/// A Rust type that has the same representation as an LLVM type, so it can be passed to
+/// and returned from JIT compiled functions.
+///
+/// # Safety
+///
+/// `matches` must only accept LLVM types the C calling convention passes like `Self`.
+pub unsafe trait JitType {
+    /// Name of the matching LLVM type, for error messages.
+    const LLVM_NAME: &'static str;
+
+    /// Whether a parameter or return value of type `ty` can be read as `Self`.
+    ///
+    /// # Safety
+    ///
+    /// `ty` has to be a valid LLVM type.
+    unsafe fn matches(ty: LLVMTypeRef, zero_extended: bool) -> bool;
 }
 
-impl ExecutionEngine {
-    pub fn new(mut module: LLVMModuleRef) -> Result<(ExecutionEngine, FrozenModule), String> {
-        let mut ee = std::ptr::null_mut();
-        let mut error = std::ptr::null_mut();
-        unsafe {
-            if LLVMCreateExecutionEngineForModule(&mut ee, module, &mut error) != 0 {
-                let error_msg = CString::from_raw(error).to_string_lossy().into_owned();
-                LLVMDisposeMessage(error);
-                return Err(error_msg);
-            }
+fn is_int(ty: LLVMTypeRef, width: u32) -> bool {
+    unsafe { LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMIntegerTypeKind && LLVMGetIntTypeWidth(ty) == width }
+}
+
+/// Whether the parameter or return value at `index` of `function` has the zeroext attribute.
+fn is_zero_extended(function: LLVMValueRef, index: LLVMAttributeIndex) -> bool {
+    unsafe {
+        let kind = LLVMGetEnumAttributeKindForName(c"zeroext".as_ptr(), "zeroext".len());
+        !LLVMGetEnumAttributeAtIndex(function, index, kind).is_null()
+    }
+}
+
+/// The signature of `function` the way `JitSignature::describe` writes it.
+fn describe_function(function: LLVMValueRef) -> String {
+    let describe = |ty, index| {
+        let name = unsafe { take_message(LLVMPrintTypeToString(ty)) };
+        if is_zero_extended(function, index) {
+            format!("zeroext {}", name)
+        } else {
+            name
         }
+    };
 
-        Ok((ExecutionEngine { ee }, FrozenModule { module }))
+    unsafe {
+        let function_type = LLVMGlobalGetValueType(function);
+        let mut params = vec![std::ptr::null_mut(); LLVMCountParamTypes(function_type) as usize];
+        LLVMGetParamTypes(function_type, params.as_mut_ptr());
+
+        let params: Vec<_> = params.iter().enumerate().map(|(i, &param)| describe(param, i as c_uint + 1)).collect();
+        format!("{} ({})", describe(LLVMGetReturnType(function_type), LLVMAttributeReturnIndex), params.join(", "))
     }
+}
 
-    pub fn new_interpreter(mut module: LLVMModuleRef) -> Result<(ExecutionEngine, FrozenModule), String> {
-        let mut ee = std::ptr::null_mut();
-        let mut error = std::ptr::null_mut();
-        unsafe {
-            if LLVMCreateInterpreterForModule(&mut ee, module, &mut error) != 0 {
-                let error_msg = CString::from_raw(error).to_string_lossy().into_owned();
-                LLVMDisposeMessage(error);
-                return Err(error_msg);
+macro_rules! jit_types {
+    ($($ty:ty => $name:expr, |$llvm:pat_param, $zero_extended:pat_param| $matches:expr;)*) => {
+        $(
+            unsafe impl JitType for $ty {
+                const LLVM_NAME: &'static str = $name;
+
+                unsafe fn matches($llvm: LLVMTypeRef, $zero_extended: bool) -> bool {
+                    $matches
+                }
+            }
+        )*
+    };
+}
+
+// only a zero extended i1 can be read as a Rust bool, LLVM leaves the other bits of a
+// plain one undefined, and the empty struct Tobichi's () is made of is returned in no
+// register, like void
+jit_types! {
+    () => "void", |ty, _| match LLVMGetTypeKind(ty) {
+        LLVMTypeKind::LLVMVoidTypeKind => true,
+        LLVMTypeKind::LLVMStructTypeKind => LLVMCountStructElementTypes(ty) == 0,
+        _ => false
+    };
+    bool => "zeroext i1", |ty, zero_extended| zero_extended && is_int(ty, 1);
+    i32 => "i32", |ty, _| is_int(ty, 32);
+    i64 => "i64", |ty, _| is_int(ty, 64);
+    u32 => "i32", |ty, _| is_int(ty, 32);
+    u64 => "i64", |ty, _| is_int(ty, 64);
+    f32 => "float", |ty, _| LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMFloatTypeKind;
+    f64 => "double", |ty, _| LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMDoubleTypeKind;
+}
+
+/// An `extern "C" fn` type a JIT compiled function can be called as.
+///
+/// # Safety
+///
+/// `matches` must only accept functions that can be called through `Self`.
+pub unsafe trait JitSignature: Copy {
+    /// The arguments as a tuple.
+    type Args;
+    type Output;
+
+    /// The signature as LLVM prints it, for error messages.
+    fn describe() -> String;
+
+    /// Whether `function` has a signature `Self` can call.
+    ///
+    /// # Safety
+    ///
+    /// `function` has to be a valid LLVM function.
+    unsafe fn matches(function: LLVMValueRef) -> bool;
+
+    /// # Safety
+    ///
+    /// `address` has to point to a function of a type `matches` accepts.
+    unsafe fn from_address(address: u64) -> Self;
+
+    fn to_address(self) -> u64;
+
+    fn call(self, args: Self::Args) -> Self::Output;
+}
+
+macro_rules! jit_signatures {
+    ($(($($arg:ident),*))*) => {
+        $(
+            unsafe impl<R: JitType, $($arg: JitType),*> JitSignature for extern "C" fn($($arg),*) -> R {
+                type Args = ($($arg,)*);
+                type Output = R;
+
+                fn describe() -> String {
+                    let args: &[&str] = &[$($arg::LLVM_NAME),*];
+                    format!("{} ({})", R::LLVM_NAME, args.join(", "))
+                }
+
+                unsafe fn matches(function: LLVMValueRef) -> bool {
+                    let expected: &[unsafe fn(LLVMTypeRef, bool) -> bool] = &[$($arg::matches),*];
+                    let function_type = LLVMGlobalGetValueType(function);
+                    let mut params = vec![std::ptr::null_mut(); LLVMCountParamTypes(function_type) as usize];
+                    LLVMGetParamTypes(function_type, params.as_mut_ptr());
+
+                    LLVMIsFunctionVarArg(function_type) == 0
+                        && params.len() == expected.len()
+                        && params.iter().zip(expected).enumerate()
+                            .all(|(i, (&param, matches))| matches(param, is_zero_extended(function, i as c_uint + 1)))
+                        && R::matches(LLVMGetReturnType(function_type), is_zero_extended(function, LLVMAttributeReturnIndex))
+                }
+
+                unsafe fn from_address(address: u64) -> Self {
+                    std::mem::transmute::<usize, Self>(address as usize)
+                }
+
+                fn to_address(self) -> u64 {
+                    self as usize as u64
+                }
+
+                #[allow(non_snake_case)]
+                fn call(self, args: Self::Args) -> R {
+                    let ($($arg,)*) = args;
+                    self($($arg),*)
+                }
             }
+        )*
+    };
+}
+
+jit_signatures! {
+    ()
+    (A)
+    (A, B)
+    (A, B, C)
+    (A, B, C, D)
+    (A, B, C, D, E)
+    (A, B, C, D, E, F)
+}
+
+/// A JIT compiled function. It borrows the engine, so it cannot be called once the
+/// engine is gone, and the code defining it can only be removed by unsafe methods.
+pub struct JitFunction<'ee, F> {
+    function: F,
+    _engine: PhantomData<&'ee ()>,
+}
+
+impl<F: JitSignature> JitFunction<'_, F> {
+    /// Calls the function with a tuple of its arguments.
+    pub fn call(&self, args: F::Args) -> F::Output {
+        self.function.call(args)
+    }
+
+    /// The function pointer itself.
+    ///
+    /// # Safety
+    ///
+    /// It must not be called after the engine is dropped or the code defining it removed.
+    pub unsafe fn as_raw(&self) -> F {
+        self.function
+    }
+}
+
+/// A Rust function that JIT compiled code can call through an `extern` declaration.
+#[derive(Clone)]
+pub struct HostFunction {
+    address: u64,
+    signature: String,
+    matches: unsafe fn(LLVMValueRef) -> bool,
+}
+
+impl HostFunction {
+    pub fn new<F: JitSignature>(function: F) -> HostFunction {
+        HostFunction {
+            address: function.to_address(),
+            signature: F::describe(),
+            matches: F::matches,
         }
+    }
 
-        Ok((ExecutionEngine { ee }, FrozenModule { module }))
+    pub fn address(&self) -> u64 {
+        self.address
     }
+}
 
-    pub fn new_jit_compiler(
-        mut module: LLVMModuleRef,
-        opt_level: u32,
-    ) -> Result<(ExecutionEngine, FrozenModule), String> {
-        let mut ee = std::ptr::null_mut();
-        let mut error = std::ptr::null_mut();
-        unsafe {
-            if LLVMCreateJITCompilerForModule(&mut ee, module, opt_level, &mut error) != 0 {
-                let error_msg = CString::from_raw(error).to_string_lossy().into_owned();
-                LLVMDisposeMessage(error);
-                return Err(error_msg);
+/// An engine compiled functions can be looked up in.
+pub trait Jit {
+    /// The function `name`, with the attributes of its parameters and return value, if
+    /// the engine has it.
+    ///
+    /// # Safety
+    ///
+    /// The result must not be used once the function is removed or redefined.
+    unsafe fn function(&self, name: &str) -> Option<LLVMValueRef>;
+
+    /// Address of the function `name`, compiling it if needed.
+    fn function_address(&self, name: &str) -> Result<u64, String>;
+
+    /// Looks up the function `name` as the function pointer type `F`, which has to match
+    /// its LLVM signature.
+    fn get_function<F: JitSignature>(&self, name: &str) -> Result<JitFunction<'_, F>, String> {
+        let function = unsafe { self.function(name) }.ok_or_else(|| format!("no function named {}", name))?;
+        if !unsafe { F::matches(function) } {
+            return Err(format!("function {} has type {}, not {}", name, describe_function(function), F::describe()));
+        }
+
+        let address = self.function_address(name)?;
+        Ok(JitFunction {
+            function: unsafe { F::from_address(address) },
+            _engine: PhantomData,
+        })
+    }
+}
+
+/// Checks that the `extern` declarations of `module` agree with the host functions
+/// they refer to.
+pub fn verify_host_functions(module: &Module, host_functions: &HashMap<String, HostFunction>) -> Result<(), String> {
+    unsafe {
+        let mut function = LLVMGetFirstFunction(module.to_ref());
+        while !function.is_null() {
+            let mut length = 0;
+            let name = LLVMGetValueName2(function, &mut length);
+            let name = String::from_utf8_lossy(std::slice::from_raw_parts(name as *const u8, length));
+
+            if let Some(host) = host_functions.get(name.as_ref()) {
+                if LLVMIsDeclaration(function) == 0 {
+                    return Err(format!("function {} is defined, but also registered as a host function", name));
+                }
+                if !(host.matches)(function) {
+                    let declared = describe_function(function);
+                    return Err(format!("extern {} has type {}, but the host function has type {}", name, declared, host.signature));
+                }
             }
+            function = LLVMGetNextFunction(function);
         }
+    }
+
+    Ok(())
+}
+
+/// An LLVM execution engine. Disposing it disposes every module it still owns, the
+/// LLVM context of those modules is kept alive until then.
+pub struct ExecutionEngine {
+    ee: LLVMExecutionEngineRef,
+    context: Rc<ContextHandle>,
+    host_functions: RefCell<HashMap<String, HostFunction>>,
+    /// Declarations the host functions are mapped through, which the engine refers to
+    /// for as long as it lives.
+    host_declarations: Module,
+}
+
+/// Calls an LLVM function creating an execution engine for `module`. The module is
+/// owned by LLVM from then on, even when creating the engine fails.
+fn create_engine<F>(module: Module, create: F) -> Result<ExecutionEngine, String>
+where F: FnOnce(&mut LLVMExecutionEngineRef, LLVMModuleRef, &mut *mut c_char) -> LLVMBool {
+    let (module, context) = module.into_raw();
+    let mut ee = std::ptr::null_mut();
+    let mut error = std::ptr::null_mut();
 
-        Ok((ExecutionEngine { ee }, FrozenModule { module }))
+    if create(&mut ee, module, &mut error) != 0 {
+        return Err(unsafe { take_message(error) });
     }
 
-    pub fn add_module(&mut self, module: LLVMModuleRef) -> FrozenModule {
+    // names are mangled with the data layout of the module declaring them
+    let host_declarations = Module::new("__host_functions", &context);
+    unsafe {
+        LLVMSetModuleDataLayout(host_declarations.to_ref(), LLVMGetExecutionEngineTargetData(ee));
+    }
+
+    Ok(ExecutionEngine {
+        ee,
+        context,
+        host_functions: RefCell::new(HashMap::new()),
+        host_declarations,
+    })
+}
+
+impl ExecutionEngine {
+    pub fn new(module: Module) -> Result<ExecutionEngine, String> {
+        create_engine(module, |ee, module, error| unsafe {
+            LLVMCreateExecutionEngineForModule(ee, module, error)
+        })
+    }
+
+    pub fn new_interpreter(module: Module) -> Result<ExecutionEngine, String> {
+        create_engine(module, |ee, module, error| unsafe {
+            LLVMCreateInterpreterForModule(ee, module, error)
+        })
+    }
+
+    pub fn new_jit_compiler(module: Module, opt_level: u32) -> Result<ExecutionEngine, String> {
+        create_engine(module, |ee, module, error| unsafe {
+            LLVMCreateJITCompilerForModule(ee, module, opt_level, error)
+        })
+    }
+
+    /// Hands `module` to the engine. It has to come from the same LLVM context as the
+    /// module the engine was created with.
+    pub fn add_module(&self, module: Module) -> FrozenModule<'_> {
+        assert!(Rc::ptr_eq(module.context(), &self.context), "module belongs to another LLVM context");
+
+        let (module, _) = module.into_raw();
         unsafe {
             LLVMAddModule(self.to_ref(), module);
         }
-        FrozenModule { module }
+
+        FrozenModule { module, ee: self.ee, _engine: PhantomData }
     }
 
-    pub fn remove_module(&mut self, module: FrozenModule) -> Result<LLVMModuleRef, String> {
+    /// Takes a module added to this engine back out of it.
+    ///
+    /// # Safety
+    ///
+    /// No function looked up in the module may be called afterwards.
+    pub unsafe fn remove_module(&self, module: FrozenModule<'_>) -> Result<Module, String> {
+        assert!(module.ee == self.ee, "module belongs to another execution engine");
+
         let mut error = std::ptr::null_mut();
         let mut module_ref = std::ptr::null_mut();
+        if LLVMRemoveModule(self.to_ref(), module.get(), &mut module_ref, &mut error) != 0 {
+            return Err(take_message(error));
+        }
+
+        Ok(Module::from_raw(module_ref, self.context.clone()))
+    }
+
+    /// Makes calls to the external function `name` go to `function`, in every module
+    /// added from now on. The code declares it as usual, with `extern name(...)`.
+    pub fn add_host_function(&self, name: &str, function: HostFunction) -> Result<(), String> {
+        let mut host_functions = self.host_functions.borrow_mut();
+        if host_functions.contains_key(name) {
+            return Err(format!("host function {} is already registered", name));
+        }
+
+        let name_c = CString::new(name).map_err(|_| format!("invalid function name {}", name))?;
         unsafe {
-            if LLVMRemoveModule(self.to_ref(), module.get(), &mut module_ref, &mut error) != 0 {
-                let error_msg = CString::from_raw(error).to_string_lossy().into_owned();
-                LLVMDisposeMessage(error);
-                return Err(error_msg);
-            }
+            let fn_type = LLVMFunctionType(LLVMVoidTypeInContext(self.context.to_ref()), std::ptr::null_mut(), 0, 0);
+            let declaration = LLVMAddFunction(self.host_declarations.to_ref(), name_c.as_ptr(), fn_type);
+            LLVMAddGlobalMapping(self.to_ref(), declaration, function.address as usize as *mut c_void);
         }
-        Ok(module_ref)
+
+        host_functions.insert(name.to_string(), function);
+        Ok(())
+    }
+
+    /// Checks that the `extern` declarations of `module` agree with the host functions
+    /// they refer to.
+    pub fn verify_host_functions(&self, module: &Module) -> Result<(), String> {
+        verify_host_functions(module, &self.host_functions.borrow())
     }
 
     pub fn run_static_constructors(&self) {
@@ -98,9 +423,18 @@ impl ExecutionEngine {
         }
     }
 
-    pub fn run_function(&self, f: LLVMValueRef, args: &mut [LLVMGenericValueRef]) -> LLVMGenericValueRef {
+    /// Runs the function `name` with `args`. The interpreter runs any function, MCJIT only
+    /// ones without arguments or shaped like C's `main`.
+    pub fn run_function(&self, name: &str, args: &[GenericValue]) -> Result<GenericValue, String> {
+        let f = self.find_function(name).ok_or_else(|| format!("no function named {}", name))?;
+        let params = unsafe { LLVMCountParams(f) } as usize;
+        if params != args.len() {
+            return Err(format!("function {} takes {} argument(s), not {}", name, params, args.len()));
+        }
+
+        let mut args: Vec<_> = args.iter().map(GenericValue::to_ref).collect();
         unsafe {
-            LLVMRunFunction(self.to_ref(), f, args.len() as c_uint, args.as_mut_ptr())
+            Ok(GenericValue::from_raw(LLVMRunFunction(self.to_ref(), f, args.len() as c_uint, args.as_mut_ptr())))
         }
     }
 
@@ -111,7 +445,7 @@ impl ExecutionEngine {
         }
     }
 
-    pub fn find_function(&self, name: &str) -> Option<LLVMValueRef> {
+    fn find_function(&self, name: &str) -> Option<LLVMValueRef> {
         let name = CString::new(name).unwrap();
         let mut f = std::ptr::null_mut();
         unsafe {
@@ -125,11 +459,29 @@ impl ExecutionEngine {
 }
 
 impl ExecutionEngine {
-    pub fn to_ref(&self) -> LLVMExecutionEngineRef {
+    /// The raw engine.
+    ///
+    /// # Safety
+    ///
+    /// It must not be disposed, and modules added through it are not tracked by `FrozenModule`.
+    pub unsafe fn to_ref(&self) -> LLVMExecutionEngineRef {
         self.ee
     }
 }
 
+impl Jit for ExecutionEngine {
+    unsafe fn function(&self, name: &str) -> Option<LLVMValueRef> {
+        self.find_function(name)
+    }
+
+    fn function_address(&self, name: &str) -> Result<u64, String> {
+        match self.get_function_address(name) {
+            0 => Err(format!("could not compile function {}", name)),
+            address => Ok(address)
+        }
+    }
+}
+
 impl Drop for ExecutionEngine {
     fn drop(&mut self) {
         unsafe {
@@ -175,25 +527,22 @@ impl MCJITBuilder {
         self
     }
 
-    pub fn create(mut self, module: LLVMModuleRef) -> Result<(ExecutionEngine, FrozenModule), String> {
-        let mut ee = std::ptr::null_mut();
-        let mut error = std::ptr::null_mut();
-        unsafe {
-            if LLVMCreateMCJITCompilerForModule(
-                &mut ee,
+    pub fn create(mut self, module: Module) -> Result<ExecutionEngine, String> {
+        create_engine(module, |ee, module, error| unsafe {
+            LLVMCreateMCJITCompilerForModule(
+                ee,
                 module,
                 &mut self.options,
                 std::mem::size_of::<LLVMMCJITCompilerOptions>() as size_t,
-                &mut error,
-            ) != 0
-            {
-                let error_msg = CString::from_raw(error).to_string_lossy().into_owned();
-                LLVMDisposeMessage(error);
-                return Err(error_msg);
-            }
-        }
+                error,
+            )
+        })
+    }
+}
 
-        Ok((ExecutionEngine { ee }, FrozenModule { module }))
+impl Default for MCJITBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -208,3 +557,11 @@ pub fn link_in_interpreter() {
         LLVMLinkInInterpreter();
     }
 }
+
+pub fn initialize_native_target() {
+    unsafe {
+        LLVM_InitializeNativeTarget();
+        LLVM_InitializeNativeAsmPrinter();
+        LLVM_InitializeNativeAsmParser();
+    }
+}
\ No newline at end of file
@@ -1 +1,7 @@
-pub mod jitter;
\ No newline at end of file
+pub mod builder;
+pub mod jitter;
+pub mod module;
+pub mod optimize;
+pub mod orc;
+pub mod target;
+pub mod value;
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::rc::Rc;
+use libc::c_char;
+use llvm_sys::prelude::*;
+use llvm_sys::core::*;
+use llvm_sys::error::{LLVMDisposeErrorMessage, LLVMErrorRef, LLVMGetErrorMessage};
+use llvm_sys::orc2::*;
+
+/// Copies a message allocated by LLVM and frees the original. A null message gives an
+/// empty string.
+///
+/// # Safety
+///
+/// `message` has to come from LLVM and must not be used afterwards.
+pub unsafe fn take_message(message: *mut c_char) -> String {
+    if message.is_null() {
+        return String::new();
+    }
+
+    let copy = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeMessage(message);
+    copy
+}
+
+/// Consumes an error returned by LLVM and gives its message.
+///
+/// # Safety
+///
+/// `error` has to be a failure, not null, and must not be used afterwards.
+pub unsafe fn take_error(error: LLVMErrorRef) -> String {
+    let message = LLVMGetErrorMessage(error);
+    let copy = CStr::from_ptr(message).to_string_lossy().into_owned();
+    LLVMDisposeErrorMessage(message);
+    copy
+}
+
+/// An LLVM context. It is shared by the code generator and every module created in it,
+/// so it is only disposed once nothing refers to it anymore.
+pub struct ContextHandle {
+    context: LLVMContextRef,
+    thread_safe: LLVMOrcThreadSafeContextRef,
+}
+
+impl ContextHandle {
+    pub fn new() -> Rc<ContextHandle> {
+        Rc::new(ContextHandle {
+            context: unsafe { LLVMContextCreate() },
+            thread_safe: std::ptr::null_mut(),
+        })
+    }
+
+    /// A context the ORC JIT can compile modules of. ORC keeps it alive as long as it
+    /// holds such a module.
+    pub fn new_thread_safe() -> Rc<ContextHandle> {
+        unsafe {
+            let thread_safe = LLVMOrcCreateNewThreadSafeContext();
+            Rc::new(ContextHandle {
+                context: LLVMOrcThreadSafeContextGetContext(thread_safe),
+                thread_safe,
+            })
+        }
+    }
+
+    /// The ORC handle of the context, null unless it was created by `new_thread_safe`.
+    pub fn thread_safe(&self) -> LLVMOrcThreadSafeContextRef {
+        self.thread_safe
+    }
+
+    pub fn to_ref(&self) -> LLVMContextRef {
+        self.context
+    }
+}
+
+impl Drop for ContextHandle {
+    fn drop(&mut self) {
+        unsafe {
+            if self.thread_safe.is_null() {
+                LLVMContextDispose(self.context);
+            } else {
+                LLVMOrcDisposeThreadSafeContext(self.thread_safe);
+            }
+        }
+    }
+}
+
+/// A module owned by Rust. It is disposed when dropped, unless it was handed to an
+/// `ExecutionEngine`, which then owns it until it is removed again.
+pub struct Module {
+    module: LLVMModuleRef,
+    context: Rc<ContextHandle>,
+}
+
+impl Module {
+    pub fn new(name: &str, context: &Rc<ContextHandle>) -> Module {
+        let name = CString::new(name).unwrap();
+        let module = unsafe { LLVMModuleCreateWithNameInContext(name.as_ptr(), context.to_ref()) };
+
+        Module {
+            module,
+            context: context.clone(),
+        }
+    }
+
+    /// Takes ownership of `module`.
+    ///
+    /// # Safety
+    ///
+    /// `module` has to belong to `context` and must not be owned by anything else.
+    pub unsafe fn from_raw(module: LLVMModuleRef, context: Rc<ContextHandle>) -> Module {
+        Module { module, context }
+    }
+
+    /// Gives up ownership of the module, the caller has to dispose it.
+    pub fn into_raw(self) -> (LLVMModuleRef, Rc<ContextHandle>) {
+        let module = std::mem::ManuallyDrop::new(self);
+        unsafe {
+            (module.module, std::ptr::read(&module.context))
+        }
+    }
+
+    pub fn context(&self) -> &Rc<ContextHandle> {
+        &self.context
+    }
+
+    pub fn to_ref(&self) -> LLVMModuleRef {
+        self.module
+    }
+
+    /// Names of the functions defined (not just declared) here, in definition order.
+    pub fn defined_functions(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        unsafe {
+            let mut function = LLVMGetFirstFunction(self.module);
+            while !function.is_null() {
+                if LLVMIsDeclaration(function) == 0 {
+                    let mut length = 0;
+                    let name = LLVMGetValueName2(function, &mut length);
+                    let name = std::slice::from_raw_parts(name as *const u8, length);
+                    names.push(String::from_utf8_lossy(name).into_owned());
+                }
+                function = LLVMGetNextFunction(function);
+            }
+        }
+
+        names
+    }
+}
+
+/// Prints the module as LLVM IR.
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ir = unsafe { take_message(LLVMPrintModuleToString(self.module)) };
+        write!(f, "{}", ir)
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeModule(self.module);
+        }
+    }
+}
\ No newline at end of file
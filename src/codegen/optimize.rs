@@ -0,0 +1,162 @@
+use std::ffi::CString;
+use std::sync::Once;
+use llvm_sys::prelude::*;
+use llvm_sys::support::LLVMParseCommandLineOptions;
+use llvm_sys::transforms::pass_builder::*;
+
+use crate::codegen::module::{take_error, Module};
+use crate::codegen::target::TargetMachine;
+
+pub use self::OptLevel::{
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+    Oz
+};
+
+/// Passes that are useful on Tobichi code, for `tobichi passes`. Any pass the LLVM
+/// pass builder knows can be used in a pipeline though.
+pub const KNOWN_PASSES: &[(&str, &str)] = &[
+    ("mem2reg", "promote allocas to SSA registers"),
+    ("sroa", "scalar replacement of aggregates, also promotes allocas"),
+    ("instcombine", "combine redundant instructions"),
+    ("reassociate", "reassociate commutative expressions"),
+    ("early-cse", "early common subexpression elimination"),
+    ("gvn", "global value numbering"),
+    ("sccp", "sparse conditional constant propagation"),
+    ("simplifycfg", "merge and remove basic blocks"),
+    ("dce", "dead code elimination"),
+    ("adce", "aggressive dead code elimination"),
+    ("tailcallelim", "turn tail recursion into loops"),
+    ("inline", "inline function calls"),
+    ("loop-simplify", "canonicalize loops"),
+    ("loop-rotate", "rotate loops"),
+    ("licm", "hoist loop invariant code"),
+    ("indvars", "canonicalize induction variables"),
+    ("loop-unroll", "unroll loops"),
+    ("loop-deletion", "delete loops without side effects"),
+    ("verify", "check the module is well formed"),
+    ("print", "print the module to stderr"),
+];
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+    Oz
+}
+
+impl OptLevel {
+    /// Parses the part after `-O`.
+    pub fn from_name(name: &str) -> Option<OptLevel> {
+        match name {
+            "0" => Some(O0),
+            "1" => Some(O1),
+            "2" => Some(O2),
+            "3" => Some(O3),
+            "s" => Some(Os),
+            "z" => Some(Oz),
+            _ => None
+        }
+    }
+
+    /// The pass builder's preset pipeline for this level.
+    pub fn pipeline(&self) -> &'static str {
+        match *self {
+            O0 => "default<O0>",
+            O1 => "default<O1>",
+            O2 => "default<O2>",
+            O3 => "default<O3>",
+            Os => "default<Os>",
+            Oz => "default<Oz>"
+        }
+    }
+
+    /// Level for the machine code generator, size levels generate code like `-O2`.
+    pub fn codegen_level(&self) -> u32 {
+        match *self {
+            O0 => 0,
+            O1 => 1,
+            O2 | Os | Oz => 2,
+            O3 => 3
+        }
+    }
+}
+
+/// Makes LLVM print the module after every pass it runs, to stderr. This is an LLVM
+/// wide option, so once enabled it stays on for the rest of the process.
+pub fn enable_print_after_all() {
+    static ENABLE: Once = Once::new();
+
+    ENABLE.call_once(|| {
+        let args = [c"tobichi".as_ptr(), c"-print-after-all".as_ptr()];
+        unsafe {
+            LLVMParseCommandLineOptions(args.len() as i32, args.as_ptr(), std::ptr::null());
+        }
+    });
+}
+
+/// An IR pass pipeline in the textual syntax of the new pass manager, either one of
+/// the presets or something like `mem2reg,instcombine,function(gvn)`.
+pub struct PassPipeline {
+    passes: String,
+    verify_each: bool,
+    debug_logging: bool,
+}
+
+impl PassPipeline {
+    pub fn new(passes: &str) -> PassPipeline {
+        PassPipeline {
+            passes: passes.to_string(),
+            verify_each: false,
+            debug_logging: false,
+        }
+    }
+
+    pub fn preset(level: OptLevel) -> PassPipeline {
+        PassPipeline::new(level.pipeline())
+    }
+
+    pub fn passes(&self) -> &str {
+        &self.passes
+    }
+
+    /// Verifies the module after every pass.
+    pub fn verify_each(mut self) -> Self {
+        self.verify_each = true;
+        self
+    }
+
+    /// Prints the name of every pass as it runs.
+    pub fn debug_logging(mut self) -> Self {
+        self.debug_logging = true;
+        self
+    }
+
+    /// Runs the pipeline over `module`. The target machine, if given, lets the passes
+    /// use what they know about the target.
+    pub fn run(&self, module: &Module, machine: Option<&TargetMachine>) -> Result<(), String> {
+        let passes = CString::new(self.passes.as_str()).map_err(|_| "invalid pass pipeline".to_string())?;
+        let machine = machine.map_or(std::ptr::null_mut(), TargetMachine::to_ref);
+
+        unsafe {
+            let options = LLVMCreatePassBuilderOptions();
+            LLVMPassBuilderOptionsSetVerifyEach(options, self.verify_each as LLVMBool);
+            LLVMPassBuilderOptionsSetDebugLogging(options, self.debug_logging as LLVMBool);
+
+            let error = LLVMRunPasses(module.to_ref(), passes.as_ptr(), machine, options);
+            LLVMDisposePassBuilderOptions(options);
+
+            if !error.is_null() {
+                return Err(take_error(error));
+            }
+        }
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,431 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::rc::Rc;
+use libc::{c_char, c_uint, c_void};
+use llvm_sys::prelude::*;
+use llvm_sys::error::LLVMErrorRef;
+use llvm_sys::core::*;
+use llvm_sys::orc2::*;
+use llvm_sys::orc2::lljit::*;
+use llvm_sys::LLVMAttributeReturnIndex;
+
+use crate::codegen::jitter::{verify_host_functions, HostFunction, Jit};
+use crate::codegen::module::{take_error, take_message, ContextHandle, Module};
+
+/// Symbols the ORC engine defines, exported functions that can be called.
+const FUNCTION_FLAGS: LLVMJITSymbolFlags = LLVMJITSymbolFlags {
+    GenericFlags: LLVMJITSymbolGenericFlags::LLVMJITSymbolGenericFlagsExported as u8
+        | LLVMJITSymbolGenericFlags::LLVMJITSymbolGenericFlagsCallable as u8,
+    TargetFlags: 0,
+};
+
+/// Where calls go when a function fails to compile lazily, unless the engine was given
+/// another fallback. The reason has been recorded at that point and there is nothing
+/// sensible left to return.
+extern "C" fn lazy_compilation_failed() -> ! {
+    std::process::abort();
+}
+
+/// Errors ORC reports on its own instead of returning them from a call.
+type Errors = RefCell<Vec<String>>;
+
+extern "C" fn record_error(errors: *mut c_void, error: LLVMErrorRef) {
+    unsafe {
+        (*(errors as *const Errors)).borrow_mut().push(take_error(error));
+    }
+}
+
+fn check(error: LLVMErrorRef) -> Result<(), String> {
+    if error.is_null() {
+        Ok(())
+    } else {
+        Err(unsafe { take_error(error) })
+    }
+}
+
+/// Resources added to a JITDylib under a tracker of their own, removed from the JIT
+/// when dropped.
+struct ResourceTracker {
+    tracker: LLVMOrcResourceTrackerRef,
+    errors: Rc<Errors>,
+}
+
+impl ResourceTracker {
+    fn new(dylib: LLVMOrcJITDylibRef, errors: &Rc<Errors>) -> ResourceTracker {
+        ResourceTracker {
+            tracker: unsafe { LLVMOrcJITDylibCreateResourceTracker(dylib) },
+            errors: errors.clone(),
+        }
+    }
+}
+
+impl Drop for ResourceTracker {
+    fn drop(&mut self) {
+        unsafe {
+            let error = LLVMOrcResourceTrackerRemove(self.tracker);
+            if !error.is_null() {
+                self.errors.borrow_mut().push(format!("could not unload code: {}", take_error(error)));
+            }
+            LLVMOrcReleaseResourceTracker(self.tracker);
+        }
+    }
+}
+
+/// The stable entry point of a function. Callers always call the trampoline, which
+/// jumps to the address stored in its slot: first a stub compiling the function on
+/// its first call, later whatever the function was redefined as.
+struct Trampoline {
+    function_type: LLVMTypeRef,
+    // a declaration in the engine's own module, with the attributes lookups check
+    declaration: LLVMValueRef,
+    slot: *mut u64,
+    _code: ResourceTracker,
+}
+
+/// A JIT built on LLVM's ORC v2 LLJIT. All code of a session lives in the main JITDylib,
+/// functions are compiled when first called, and defining a function again replaces it
+/// everywhere, unloading the old code once nothing in the JIT needs it.
+///
+/// Lazy compilation works per module, the REPL puts each function in a module of its
+/// own. Stubs and trampolines are small and kept until the engine is dropped.
+pub struct OrcEngine {
+    jit: LLVMOrcLLJITRef,
+    session: LLVMOrcJITDylibRef,
+    call_through: LLVMOrcLazyCallThroughManagerRef,
+    stubs: LLVMOrcIndirectStubsManagerRef,
+    context: Rc<ContextHandle>,
+    declarations: Module,
+    // the code of every defined function, shared by the functions of one module so it
+    // is unloaded once all of them have been redefined or removed
+    definitions: HashMap<String, Rc<ResourceTracker>>,
+    trampolines: HashMap<String, Trampoline>,
+    host_functions: HashMap<String, HostFunction>,
+    versions: usize,
+    errors: Rc<Errors>,
+}
+
+impl OrcEngine {
+    /// Creates an engine for the host. The native target has to be initialized. Calling a
+    /// function that fails to compile aborts the process.
+    pub fn new() -> Result<OrcEngine, String> {
+        OrcEngine::with_fallback(lazy_compilation_failed)
+    }
+
+    /// Creates an engine where calling a function that fails to compile runs `fallback`
+    /// instead, after the reason has been recorded in `take_errors`.
+    pub fn with_fallback(fallback: extern "C" fn() -> !) -> Result<OrcEngine, String> {
+        let mut jit = std::ptr::null_mut();
+        check(unsafe { LLVMOrcCreateLLJIT(&mut jit, std::ptr::null_mut()) })?;
+
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        unsafe {
+            let session = LLVMOrcLLJITGetMainJITDylib(jit);
+            LLVMOrcExecutionSessionSetErrorReporter(
+                LLVMOrcLLJITGetExecutionSession(jit),
+                record_error,
+                Rc::as_ptr(&errors) as *mut c_void,
+            );
+
+            // externs like sin and cos come from the process
+            let mut generator = std::ptr::null_mut();
+            let error = LLVMOrcCreateDynamicLibrarySearchGeneratorForProcess(
+                &mut generator,
+                LLVMOrcLLJITGetGlobalPrefix(jit),
+                None,
+                std::ptr::null_mut(),
+            );
+            if let Err(message) = check(error) {
+                LLVMOrcDisposeLLJIT(jit);
+                return Err(message);
+            }
+            LLVMOrcJITDylibAddGenerator(session, generator);
+
+            let triple = LLVMOrcLLJITGetTripleString(jit);
+            let mut call_through = std::ptr::null_mut();
+            let error = LLVMOrcCreateLocalLazyCallThroughManager(
+                triple,
+                LLVMOrcLLJITGetExecutionSession(jit),
+                fallback as usize as LLVMOrcJITTargetAddress,
+                &mut call_through,
+            );
+            if let Err(message) = check(error) {
+                LLVMOrcDisposeLLJIT(jit);
+                return Err(message);
+            }
+
+            let context = ContextHandle::new_thread_safe();
+            Ok(OrcEngine {
+                jit,
+                session,
+                call_through,
+                stubs: LLVMOrcCreateLocalIndirectStubsManager(triple),
+                declarations: Module::new("__declarations", &context),
+                context,
+                definitions: HashMap::new(),
+                trampolines: HashMap::new(),
+                host_functions: HashMap::new(),
+                versions: 0,
+                errors,
+            })
+        }
+    }
+
+    /// Takes the errors ORC reported outside of the calls that caused them, such as a
+    /// function failing to compile lazily or old code failing to unload.
+    pub fn take_errors(&self) -> Vec<String> {
+        self.errors.take()
+    }
+
+    /// The LLVM context modules for this engine have to be created in.
+    pub fn context(&self) -> &Rc<ContextHandle> {
+        &self.context
+    }
+
+    /// Checks that `add_module` would take `module`: its externs agree with the host
+    /// functions, and a function is only redefined with the type it had before, since
+    /// code compiled against the old definition keeps calling it that way.
+    pub fn verify_module(&self, module: &Module) -> Result<(), String> {
+        verify_host_functions(module, &self.host_functions)?;
+
+        for (name, _, function_type) in defined_functions(module) {
+            if let Some(trampoline) = self.trampolines.get(&name) {
+                if trampoline.function_type != function_type {
+                    let (old, new) = unsafe {
+                        (take_message(LLVMPrintTypeToString(trampoline.function_type)), take_message(LLVMPrintTypeToString(function_type)))
+                    };
+                    return Err(format!("function {} of type {} cannot be redefined with type {}", name, old, new));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the functions defined in `module`, replacing earlier definitions of the same
+    /// name. See `verify_module` for what it has to fulfill.
+    pub fn add_module(&mut self, module: Module) -> Result<(), String> {
+        assert!(Rc::ptr_eq(module.context(), &self.context), "module belongs to another LLVM context");
+        self.verify_module(&module)?;
+        let functions = defined_functions(&module);
+
+        self.versions += 1;
+        let version = self.versions;
+
+        // the definitions get names of their own and every call, even a recursive one,
+        // goes through the trampoline, so a redefinition reaches all callers
+        for (name, function, function_type) in functions.iter() {
+            let implementation = format!("{}.{}", name, version);
+            unsafe {
+                LLVMSetValueName2(*function, implementation.as_ptr() as *const c_char, implementation.len());
+                let declaration = LLVMAddFunction(module.to_ref(), CString::new(name.as_str()).unwrap().as_ptr(), *function_type);
+                copy_attributes(*function, declaration);
+                LLVMReplaceAllUsesWith(*function, declaration);
+            }
+        }
+
+        let code = Rc::new(ResourceTracker::new(self.session, &self.errors));
+        let (module, _) = module.into_raw();
+        unsafe {
+            let module = LLVMOrcCreateNewThreadSafeModule(module, self.context.thread_safe());
+            check(LLVMOrcLLJITAddLLVMIRModuleWithRT(self.jit, code.tracker, module))?;
+        }
+
+        for (name, function, function_type) in functions {
+            let implementation = format!("{}.{}", name, version);
+            let stub = self.add_lazy_stub(&implementation)?;
+            if !self.trampolines.contains_key(&name) {
+                let trampoline = self.add_trampoline(&name, function, function_type)?;
+                self.trampolines.insert(name.clone(), trampoline);
+            }
+
+            unsafe {
+                *self.trampolines[&name].slot = stub;
+            }
+            self.definitions.insert(name, code.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Unloads the function `name`, all code of its module is gone once its other
+    /// functions are removed or redefined too.
+    ///
+    /// # Safety
+    ///
+    /// No code that calls `name` may run afterwards.
+    pub unsafe fn remove_function(&mut self, name: &str) -> Result<(), String> {
+        self.definitions.remove(name).ok_or_else(|| format!("no function named {}", name))?;
+        if let Some(trampoline) = self.trampolines.remove(name) {
+            LLVMDeleteFunction(trampoline.declaration);
+        }
+        Ok(())
+    }
+
+    /// Makes calls to the external function `name` go to `function`.
+    pub fn add_host_function(&mut self, name: &str, function: HostFunction) -> Result<(), String> {
+        if self.host_functions.contains_key(name) || self.trampolines.contains_key(name) {
+            return Err(format!("host function {} is already registered", name));
+        }
+
+        let name_c = CString::new(name).map_err(|_| format!("invalid function name {}", name))?;
+        unsafe {
+            let mut symbol = LLVMOrcCSymbolMapPair {
+                Name: LLVMOrcLLJITMangleAndIntern(self.jit, name_c.as_ptr()),
+                Sym: LLVMJITEvaluatedSymbol {
+                    Address: function.address(),
+                    Flags: FUNCTION_FLAGS,
+                },
+            };
+            check(LLVMOrcJITDylibDefine(self.session, LLVMOrcAbsoluteSymbols(&mut symbol, 1)))?;
+        }
+
+        self.host_functions.insert(name.to_string(), function);
+        Ok(())
+    }
+
+    /// Defines `{implementation}.lazy`, a stub compiling `implementation` when it is first
+    /// called, and returns its address.
+    fn add_lazy_stub(&self, implementation: &str) -> Result<u64, String> {
+        let stub = format!("{}.lazy", implementation);
+        let stub_c = CString::new(stub.as_str()).unwrap();
+        let implementation_c = CString::new(implementation).unwrap();
+
+        unsafe {
+            let mut alias = LLVMOrcCSymbolAliasMapPair {
+                Name: LLVMOrcLLJITMangleAndIntern(self.jit, stub_c.as_ptr()),
+                Entry: LLVMOrcCSymbolAliasMapEntry {
+                    Name: LLVMOrcLLJITMangleAndIntern(self.jit, implementation_c.as_ptr()),
+                    Flags: FUNCTION_FLAGS,
+                },
+            };
+            let reexport = LLVMOrcLazyReexports(self.call_through, self.stubs, self.session, &mut alias, 1);
+            check(LLVMOrcJITDylibDefine(self.session, reexport))?;
+        }
+
+        self.lookup(&stub)
+    }
+
+    /// Compiles the trampoline of `name` right away, it only loads its slot and jumps.
+    fn add_trampoline(&self, name: &str, function: LLVMValueRef, function_type: LLVMTypeRef) -> Result<Trampoline, String> {
+        let module = Module::new(&format!("{}.trampoline", name), &self.context);
+        let slot_name = format!("{}.slot", name);
+
+        let declaration = unsafe {
+            let declaration = LLVMAddFunction(self.declarations.to_ref(), CString::new(name).unwrap().as_ptr(), function_type);
+            copy_attributes(function, declaration);
+            declaration
+        };
+
+        unsafe {
+            let context = self.context.to_ref();
+            let pointer_type = LLVMPointerType(function_type, 0);
+            let slot = LLVMAddGlobal(module.to_ref(), pointer_type, CString::new(slot_name.as_str()).unwrap().as_ptr());
+            LLVMSetInitializer(slot, LLVMConstNull(pointer_type));
+
+            let trampoline = LLVMAddFunction(module.to_ref(), CString::new(name).unwrap().as_ptr(), function_type);
+            copy_attributes(function, trampoline);
+
+            let builder = LLVMCreateBuilderInContext(context);
+            LLVMPositionBuilderAtEnd(builder, LLVMAppendBasicBlockInContext(context, trampoline, c"entry".as_ptr()));
+            let target = LLVMBuildLoad2(builder, pointer_type, slot, c"target".as_ptr());
+            let mut args: Vec<_> = (0..LLVMCountParams(trampoline)).map(|i| LLVMGetParam(trampoline, i)).collect();
+            let call = LLVMBuildCall2(builder, function_type, target, args.as_mut_ptr(), args.len() as c_uint, c"".as_ptr());
+            LLVMSetTailCall(call, 1);
+            if LLVMGetTypeKind(LLVMGetReturnType(function_type)) == llvm_sys::LLVMTypeKind::LLVMVoidTypeKind {
+                LLVMBuildRetVoid(builder);
+            } else {
+                LLVMBuildRet(builder, call);
+            }
+            LLVMDisposeBuilder(builder);
+        }
+
+        let code = ResourceTracker::new(self.session, &self.errors);
+        let (module, _) = module.into_raw();
+        unsafe {
+            let module = LLVMOrcCreateNewThreadSafeModule(module, self.context.thread_safe());
+            check(LLVMOrcLLJITAddLLVMIRModuleWithRT(self.jit, code.tracker, module))?;
+        }
+
+        Ok(Trampoline {
+            function_type,
+            declaration,
+            slot: self.lookup(&slot_name)? as usize as *mut u64,
+            _code: code,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<u64, String> {
+        let name = CString::new(name).map_err(|_| format!("invalid symbol name {}", name))?;
+        let mut address = 0;
+        check(unsafe { LLVMOrcLLJITLookup(self.jit, &mut address, name.as_ptr()) })?;
+        Ok(address)
+    }
+}
+
+/// Name, value and type of every function `module` defines.
+fn defined_functions(module: &Module) -> Vec<(String, LLVMValueRef, LLVMTypeRef)> {
+    module.defined_functions().into_iter().map(|name| unsafe {
+        let function = LLVMGetNamedFunction(module.to_ref(), CString::new(name.as_str()).unwrap().as_ptr());
+        (name, function, LLVMGlobalGetValueType(function))
+    }).collect()
+}
+
+/// Gives `to` the return and parameter attributes of `from`, which has the same type.
+fn copy_attributes(from: LLVMValueRef, to: LLVMValueRef) {
+    unsafe {
+        for index in LLVMAttributeReturnIndex..=LLVMCountParams(from) {
+            let mut attributes = vec![std::ptr::null_mut(); LLVMGetAttributeCountAtIndex(from, index) as usize];
+            LLVMGetAttributesAtIndex(from, index, attributes.as_mut_ptr());
+            for attribute in attributes {
+                LLVMAddAttributeAtIndex(to, index, attribute);
+            }
+        }
+    }
+}
+
+impl Jit for OrcEngine {
+    unsafe fn function(&self, name: &str) -> Option<LLVMValueRef> {
+        self.trampolines.get(name).map(|trampoline| trampoline.declaration)
+    }
+
+    fn function_address(&self, name: &str) -> Result<u64, String> {
+        self.lookup(name)
+    }
+}
+
+impl OrcEngine {
+    /// Unloads all code and disposes the JIT, giving the errors dropping the engine
+    /// would ignore.
+    pub fn shutdown(mut self) -> Result<(), String> {
+        self.dispose();
+        match self.errors.take() {
+            errors if errors.is_empty() => Ok(()),
+            errors => Err(errors.join("\n"))
+        }
+    }
+
+    fn dispose(&mut self) {
+        if self.jit.is_null() {
+            return;
+        }
+
+        self.definitions.clear();
+        self.trampolines.clear();
+        unsafe {
+            let error = LLVMOrcDisposeLLJIT(self.jit);
+            if !error.is_null() {
+                self.errors.borrow_mut().push(format!("could not shut down the JIT: {}", take_error(error)));
+            }
+            LLVMOrcDisposeIndirectStubsManager(self.stubs);
+            LLVMOrcDisposeLazyCallThroughManager(self.call_through);
+        }
+        self.jit = std::ptr::null_mut();
+    }
+}
+
+impl Drop for OrcEngine {
+    fn drop(&mut self) {
+        self.dispose();
+    }
+}
\ No newline at end of file
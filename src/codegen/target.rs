@@ -0,0 +1,151 @@
+use std::ffi::CString;
+use libc::c_char;
+use llvm_sys::core::*;
+use llvm_sys::bit_writer::LLVMWriteBitcodeToFile;
+use llvm_sys::target::*;
+use llvm_sys::target_machine::*;
+
+use crate::codegen::module::{take_message, Module};
+
+pub use llvm_sys::target_machine::LLVMCodeGenFileType;
+
+/// Registers every target LLVM was built with, needed before cross compiling.
+pub fn initialize_all_targets() {
+    unsafe {
+        LLVM_InitializeAllTargetInfos();
+        LLVM_InitializeAllTargets();
+        LLVM_InitializeAllTargetMCs();
+        LLVM_InitializeAllAsmPrinters();
+    }
+}
+
+/// Triple of the host the compiler is running on.
+pub fn host_triple() -> String {
+    unsafe {
+        take_message(LLVMGetDefaultTargetTriple())
+    }
+}
+
+/// Writes `module` as LLVM bitcode.
+pub fn write_bitcode_to_file(module: &Module, path: &str) -> Result<(), String> {
+    let c_path = CString::new(path).unwrap();
+    unsafe {
+        if LLVMWriteBitcodeToFile(module.to_ref(), c_path.as_ptr()) != 0 {
+            return Err(format!("could not write {}", path));
+        }
+    }
+
+    Ok(())
+}
+
+pub struct TargetMachine {
+    machine: LLVMTargetMachineRef,
+}
+
+impl TargetMachine {
+    /// Target machine for the host the compiler is running on.
+    pub fn host() -> Result<TargetMachine, String> {
+        unsafe {
+            LLVM_InitializeNativeTarget();
+            LLVM_InitializeNativeAsmPrinter();
+        }
+
+        TargetMachine::create(&host_triple(), true)
+    }
+
+    /// Target machine for `triple`, the CPU is the generic one unless the triple is the host's.
+    pub fn new(triple: &str) -> Result<TargetMachine, String> {
+        initialize_all_targets();
+
+        let triple = unsafe {
+            let triple = CString::new(triple).unwrap();
+            take_message(LLVMNormalizeTargetTriple(triple.as_ptr()))
+        };
+        TargetMachine::create(&triple, triple == host_triple())
+    }
+
+    fn create(triple: &str, host: bool) -> Result<TargetMachine, String> {
+        let triple = CString::new(triple).unwrap();
+        unsafe {
+            let mut target = std::ptr::null_mut();
+            let mut error = std::ptr::null_mut();
+            if LLVMGetTargetFromTriple(triple.as_ptr(), &mut target, &mut error) != 0 {
+                return Err(take_message(error));
+            }
+
+            let (cpu, features) = if host {
+                (take_message(LLVMGetHostCPUName()), take_message(LLVMGetHostCPUFeatures()))
+            } else {
+                ("generic".to_string(), String::new())
+            };
+            let cpu = CString::new(cpu).unwrap();
+            let features = CString::new(features).unwrap();
+
+            let machine = LLVMCreateTargetMachine(
+                target,
+                triple.as_ptr(),
+                cpu.as_ptr(),
+                features.as_ptr(),
+                LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+                LLVMRelocMode::LLVMRelocPIC,
+                LLVMCodeModel::LLVMCodeModelDefault,
+            );
+
+            Ok(TargetMachine { machine })
+        }
+    }
+
+    pub fn triple(&self) -> String {
+        unsafe {
+            take_message(LLVMGetTargetMachineTriple(self.machine))
+        }
+    }
+
+    /// Sets the module's triple and data layout to the ones of this machine.
+    pub fn prepare_module(&self, module: &Module) {
+        let module = module.to_ref();
+        let triple = CString::new(self.triple()).unwrap();
+        unsafe {
+            LLVMSetTarget(module, triple.as_ptr());
+            let data_layout = LLVMCreateTargetDataLayout(self.machine);
+            let layout = LLVMCopyStringRepOfTargetData(data_layout);
+            LLVMSetDataLayout(module, layout);
+            LLVMDisposeMessage(layout);
+            LLVMDisposeTargetData(data_layout);
+        }
+    }
+
+    pub fn emit_to_file(&self, module: &Module, path: &str, file_type: LLVMCodeGenFileType)
+    -> Result<(), String> {
+        self.prepare_module(module);
+
+        let path = CString::new(path).unwrap();
+        let mut error = std::ptr::null_mut();
+        unsafe {
+            if LLVMTargetMachineEmitToFile(
+                self.machine,
+                module.to_ref(),
+                path.as_ptr() as *mut c_char,
+                file_type,
+                &mut error,
+            ) != 0
+            {
+                return Err(take_message(error));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_ref(&self) -> LLVMTargetMachineRef {
+        self.machine
+    }
+}
+
+impl Drop for TargetMachine {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeTargetMachine(self.machine);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,109 @@
+use libc::c_void;
+use llvm_sys::prelude::*;
+use llvm_sys::core::{LLVMDoubleType, LLVMFloatType, LLVMInt1Type, LLVMInt32Type, LLVMInt64Type};
+use llvm_sys::execution_engine::*;
+
+/// A value passed to or returned from `ExecutionEngine::run_function`. LLVM only looks
+/// at the width of the types used here, so the global context is good enough for them.
+pub struct GenericValue {
+    value: LLVMGenericValueRef,
+}
+
+impl GenericValue {
+    fn from_int(ty: LLVMTypeRef, value: u64, signed: bool) -> GenericValue {
+        GenericValue {
+            value: unsafe { LLVMCreateGenericValueOfInt(ty, value, signed as LLVMBool) },
+        }
+    }
+
+    pub fn from_bool(value: bool) -> GenericValue {
+        GenericValue::from_int(unsafe { LLVMInt1Type() }, value as u64, false)
+    }
+
+    pub fn from_i32(value: i32) -> GenericValue {
+        GenericValue::from_int(unsafe { LLVMInt32Type() }, value as u64, true)
+    }
+
+    pub fn from_i64(value: i64) -> GenericValue {
+        GenericValue::from_int(unsafe { LLVMInt64Type() }, value as u64, true)
+    }
+
+    pub fn from_u32(value: u32) -> GenericValue {
+        GenericValue::from_int(unsafe { LLVMInt32Type() }, value as u64, false)
+    }
+
+    pub fn from_u64(value: u64) -> GenericValue {
+        GenericValue::from_int(unsafe { LLVMInt64Type() }, value, false)
+    }
+
+    pub fn from_f32(value: f32) -> GenericValue {
+        GenericValue {
+            value: unsafe { LLVMCreateGenericValueOfFloat(LLVMFloatType(), value as f64) },
+        }
+    }
+
+    pub fn from_f64(value: f64) -> GenericValue {
+        GenericValue {
+            value: unsafe { LLVMCreateGenericValueOfFloat(LLVMDoubleType(), value) },
+        }
+    }
+
+    /// Wraps a pointer, nothing is read through it until a function uses it.
+    pub fn from_ptr<T>(pointer: *mut T) -> GenericValue {
+        GenericValue {
+            value: unsafe { LLVMCreateGenericValueOfPointer(pointer as *mut c_void) },
+        }
+    }
+
+    /// Takes ownership of a value returned by LLVM.
+    ///
+    /// # Safety
+    ///
+    /// `value` must not be owned by anything else.
+    pub unsafe fn from_raw(value: LLVMGenericValueRef) -> GenericValue {
+        GenericValue { value }
+    }
+
+    /// Bit width of an integer value.
+    pub fn int_width(&self) -> u32 {
+        unsafe { LLVMGenericValueIntWidth(self.value) }
+    }
+
+    pub fn to_bool(&self) -> bool {
+        self.to_u64() & 1 == 1
+    }
+
+    /// The integer value sign extended to 64 bits.
+    pub fn to_i64(&self) -> i64 {
+        unsafe { LLVMGenericValueToInt(self.value, 1) as i64 }
+    }
+
+    /// The integer value zero extended to 64 bits.
+    pub fn to_u64(&self) -> u64 {
+        unsafe { LLVMGenericValueToInt(self.value, 0) }
+    }
+
+    pub fn to_f32(&self) -> f32 {
+        unsafe { LLVMGenericValueToFloat(LLVMFloatType(), self.value) as f32 }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        unsafe { LLVMGenericValueToFloat(LLVMDoubleType(), self.value) }
+    }
+
+    pub fn to_ptr<T>(&self) -> *mut T {
+        unsafe { LLVMGenericValueToPointer(self.value) as *mut T }
+    }
+
+    pub fn to_ref(&self) -> LLVMGenericValueRef {
+        self.value
+    }
+}
+
+impl Drop for GenericValue {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeGenericValue(self.value);
+        }
+    }
+}
\ No newline at end of file
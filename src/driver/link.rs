@@ -0,0 +1,31 @@
+use std::env;
+use std::process::Command;
+
+/// Links `objects` into the executable `output` with the system C compiler, `$CC` or
+/// `cc`. A target triple is passed on in the clang style, which needs a compiler that
+/// understands it.
+pub fn link_executable(objects: &[&str], output: &str, target: Option<&str>) -> Result<(), String> {
+    let linker = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+    let mut command = Command::new(&linker);
+    if let Some(target) = target {
+        command.arg(format!("--target={}", target));
+    }
+    command.args(objects).arg("-o").arg(output);
+    if cfg!(unix) {
+        // extern functions such as sin and cos live in libm
+        command.arg("-lm");
+    }
+
+    let result = command.output().map_err(|err| format!("could not run {}: {}", linker, err))?;
+    if !result.status.success() {
+        return Err(format!("{} failed with {}:\n{}", linker, result.status, String::from_utf8_lossy(&result.stderr).trim_end()));
+    }
+
+    Ok(())
+}
+
+/// Name of the executable built from a source file called `stem`.
+pub fn executable_name(stem: &str) -> String {
+    format!("{}{}", stem, env::consts::EXE_SUFFIX)
+}
\ No newline at end of file
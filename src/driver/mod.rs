@@ -0,0 +1,4 @@
+pub mod link;
+#[cfg(feature = "llvm")]
+pub mod repl;
+pub mod session;
\ No newline at end of file
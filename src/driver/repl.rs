@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+use std::fs;
+use libc::c_char;
+use llvm_sys::core::LLVMSetValueName2;
+
+use crate::lexer::tokens::{try_tokenize, Token};
+use crate::ast::ast::{default_parser_settings, parse, ASTNode, ParserSettings, ExternNode, FunctionsNode, Expression};
+use crate::ast::ast::{ConditionalExpr, UnaryExpr, BinaryExpr, LoopExpr, BreakExpr, ReturnExpr, VarExpr, AssignExpr};
+use crate::ast::types::{Type, Bool, I32, I64, U32, U64, F32, F64, Unit};
+use crate::codegen::builder::{Context, IRBuilder, ANONYMOUS_FUNCTION_NAME};
+use crate::codegen::optimize::{OptLevel, PassPipeline, O0};
+use crate::codegen::jitter::{initialize_native_target, link_in_mcjit, ExecutionEngine, HostFunction, Jit, JitType, MCJITBuilder};
+use crate::codegen::module::Module;
+use crate::codegen::orc::OrcEngine;
+use crate::typeck::checker::TypeChecker;
+
+pub const HELP: &str = "\
+:ast          toggle printing the AST of every input
+:ir           toggle printing the LLVM IR of every input
+:opt <level>  optimize every input from now on, <level> is 0, 1, 2, 3, s or z
+:reset        forget every definition and user-defined operator
+:load <file>  evaluate the contents of a file
+:help         show this message
+:quit         leave the REPL";
+
+/// Runs the argumentless function `name` returning `ret` and formats its result.
+pub fn call_anonymous<E: Jit>(engine: &E, name: &str, ret: Type) -> Result<String, String> {
+    fn call<R: JitType + ToString, E: Jit>(engine: &E, name: &str) -> Result<String, String> {
+        let function = engine.get_function::<extern "C" fn() -> R>(name)?;
+        Ok(function.call(()).to_string())
+    }
+
+    match ret {
+        Bool => call::<bool, E>(engine, name),
+        I32 => call::<i32, E>(engine, name),
+        I64 => call::<i64, E>(engine, name),
+        U32 => call::<u32, E>(engine, name),
+        U64 => call::<u64, E>(engine, name),
+        F32 => call::<f32, E>(engine, name),
+        F64 => call::<f64, E>(engine, name),
+        Unit => {
+            let function = engine.get_function::<extern "C" fn()>(name)?;
+            function.call(());
+            Ok("()".to_string())
+        }
+    }
+}
+
+pub enum ReplStatus {
+    /// The input was evaluated, holding the lines to print.
+    Complete(Vec<String>),
+    /// The input ends in the middle of a definition or expression.
+    NeedMore,
+    Quit
+}
+
+/// The JIT a REPL session runs on.
+enum Backend {
+    Mcjit(ExecutionEngine),
+    /// Compiles functions on their first call and lets them be defined again.
+    Orc(OrcEngine)
+}
+
+/// Incremental evaluator behind `tobichi repl`. Each top-level node is compiled into a
+/// module of its own and added to one long-lived JIT engine, anonymous expressions are
+/// run right away and removed again.
+pub struct Repl {
+    backend: Backend,
+    context: Context,
+    checker: TypeChecker,
+    settings: ParserSettings,
+    defined: HashSet<String>,
+    host_functions: Vec<(String, HostFunction)>,
+    evaluated: usize,
+    pending: String,
+    show_ast: bool,
+    show_ir: bool,
+    opt_level: OptLevel,
+}
+
+impl Repl {
+    pub fn new() -> Result<Repl, String> {
+        Repl::with_backend(false)
+    }
+
+    /// A REPL on the ORC JIT, where functions can be redefined.
+    pub fn new_orc() -> Result<Repl, String> {
+        Repl::with_backend(true)
+    }
+
+    fn with_backend(orc: bool) -> Result<Repl, String> {
+        link_in_mcjit();
+        initialize_native_target();
+
+        let (backend, context) = if orc {
+            let engine = OrcEngine::new()?;
+            let context = Context::with_handle(engine.context().clone());
+            (Backend::Orc(engine), context)
+        } else {
+            let context = Context::new();
+            let engine = MCJITBuilder::new().create(context.new_module("__repl"))?;
+            (Backend::Mcjit(engine), context)
+        };
+
+        Ok(Repl {
+            backend,
+            context,
+            checker: TypeChecker::new().allow_redefinition(),
+            settings: default_parser_settings(),
+            defined: HashSet::new(),
+            host_functions: Vec::new(),
+            evaluated: 0,
+            pending: String::new(),
+            show_ast: false,
+            show_ir: false,
+            opt_level: O0,
+        })
+    }
+
+    /// Lets code declaring `extern name(...)` call `function`, also after a `:reset`.
+    pub fn add_host_function(&mut self, name: &str, function: HostFunction) -> Result<(), String> {
+        match self.backend {
+            Backend::Mcjit(ref engine) => engine.add_host_function(name, function.clone())?,
+            Backend::Orc(ref mut engine) => engine.add_host_function(name, function.clone())?
+        }
+        self.host_functions.push((name.to_string(), function));
+        Ok(())
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Feeds one line of input, which is either a command or more source code.
+    pub fn feed(&mut self, line: &str) -> Result<ReplStatus, String> {
+        let trimmed = line.trim();
+        if !self.is_pending() && trimmed.starts_with(':') {
+            return self.command(trimmed);
+        }
+
+        self.pending.push_str(line);
+        self.pending.push('\n');
+
+        let source = std::mem::take(&mut self.pending);
+        let tokens = try_tokenize(&source).map_err(|errors| {
+            errors.iter().map(|error| error.to_string()).collect::<Vec<_>>().join("\n")
+        })?;
+
+        let (mut ast, mut rest, settings) = self.parse(&tokens)?;
+
+        // an if without else that ends the input may still get its else on the next
+        // line, so the node it ends waits like any other unfinished input
+        if let (true, Some(FunctionsNode(function)), Some(last)) = (rest.is_empty(), ast.last(), tokens.last()) {
+            if awaits_else(&function.body, last.span.end.offset) {
+                let start = function.span.start.offset;
+                let index = tokens.iter().position(|token| token.span.start.offset == start).unwrap();
+                self.settings = settings.clone();
+                ast = self.parse(&tokens[..index])?.0;
+                rest = tokens[index..].to_vec();
+            }
+        }
+
+        let output = self.evaluate(ast, settings)?;
+
+        match rest.first() {
+            Some(token) => {
+                self.pending = source[token.span.start.offset..].to_string();
+                if output.is_empty() {
+                    Ok(ReplStatus::NeedMore)
+                } else {
+                    Ok(ReplStatus::Complete(output))
+                }
+            },
+            None => Ok(ReplStatus::Complete(output))
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<ReplStatus, String> {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(index) => (&command[..index], command[index..].trim()),
+            None => (command, "")
+        };
+
+        let output = match name {
+            ":ast" => {
+                self.show_ast = !self.show_ast;
+                vec![format!("AST printing {}", if self.show_ast { "on" } else { "off" })]
+            },
+            ":ir" => {
+                self.show_ir = !self.show_ir;
+                vec![format!("IR printing {}", if self.show_ir { "on" } else { "off" })]
+            },
+            ":opt" => {
+                self.opt_level = OptLevel::from_name(argument)
+                    .ok_or_else(|| format!("unknown optimization level {}", argument))?;
+                vec![format!("optimizing with {}", self.opt_level.pipeline())]
+            },
+            ":reset" => {
+                let (show_ast, show_ir, opt_level) = (self.show_ast, self.show_ir, self.opt_level);
+                let host_functions = std::mem::take(&mut self.host_functions);
+                *self = Repl::with_backend(matches!(self.backend, Backend::Orc(_)))?;
+                self.show_ast = show_ast;
+                self.show_ir = show_ir;
+                self.opt_level = opt_level;
+                for (name, function) in host_functions {
+                    self.add_host_function(&name, function)?;
+                }
+                vec!["state cleared".to_string()]
+            },
+            ":load" => {
+                let source = fs::read_to_string(argument)
+                    .map_err(|err| format!("could not read {}: {}", argument, err))?;
+                let tokens = try_tokenize(&source).map_err(|errors| {
+                    errors.iter().map(|error| format!("{}:{}", argument, error)).collect::<Vec<_>>().join("\n")
+                })?;
+
+                let (ast, rest, settings) = self.parse(&tokens)
+                    .map_err(|message| format!("{}:{}", argument, message))?;
+                if let Some(token) = rest.first() {
+                    self.settings = settings;
+                    return Err(format!("{}:{}: unexpected end of input", argument, token.span));
+                }
+                self.evaluate(ast, settings)?
+            },
+            ":help" => vec![HELP.to_string()],
+            ":quit" | ":q" => return Ok(ReplStatus::Quit),
+            _ => return Err(format!("unknown command {}, see :help", name))
+        };
+
+        Ok(ReplStatus::Complete(output))
+    }
+
+    /// Parses `tokens` and gives the settings from before, which `evaluate` goes back to
+    /// if the input fails.
+    fn parse(&mut self, tokens: &[Token]) -> Result<(Vec<ASTNode>, Vec<Token>, ParserSettings), String> {
+        let settings = self.settings.clone();
+        match parse(tokens, &[], &mut self.settings) {
+            Ok((ast, rest)) => Ok((ast, rest, settings)),
+            Err(message) => {
+                self.settings = settings;
+                Err(message)
+            }
+        }
+    }
+
+    /// Checks and runs `ast`, which was parsed starting from `settings`. A node that fails
+    /// leaves no trace, and the nodes after it are not evaluated.
+    fn evaluate(&mut self, ast: Vec<ASTNode>, settings: ParserSettings) -> Result<Vec<String>, String> {
+        let mut output = Vec::new();
+        let mut settings = settings;
+
+        for node in ast {
+            let (name, prototype) = match node {
+                ExternNode(ref prototype) => (prototype.name.clone(), prototype.clone()),
+                FunctionsNode(ref function) => (function.prototype.name.clone(), function.prototype.clone())
+            };
+            let checker = self.checker.clone();
+            let previous = self.context.prototype(&name).cloned();
+
+            match self.evaluate_node(node, &mut output) {
+                Ok(()) => settings.add_operator(&prototype),
+                Err(message) => {
+                    self.checker = checker;
+                    self.settings = settings;
+                    self.context.forget_prototype(&name, previous);
+                    return Err(message);
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn evaluate_node(&mut self, node: ASTNode, output: &mut Vec<String>) -> Result<(), String> {
+        if let FunctionsNode(ref function) = node {
+            let name = &function.prototype.name;
+            if !name.is_empty() && self.defined.contains(name) && !matches!(self.backend, Backend::Orc(_)) {
+                return Err(format!("{}: function {} is already defined", node.span(), name));
+            }
+        }
+
+        // the checker puts instances of generic functions in front of the node
+        let mut nodes = vec![node];
+        if let Some(diagnostic) = self.checker.check(&mut nodes).into_iter().next() {
+            return Err(format!("{}: {}", diagnostic.span, diagnostic.message));
+        }
+
+        // everything is built and verified before the engine gets any of it, so instances
+        // the checker forgets again when the node fails are not left behind
+        let mut modules = Vec::new();
+        for node in nodes.iter() {
+            match self.build(node, output) {
+                Ok(module) => modules.push(module),
+                Err(message) => {
+                    // the instances built so far are new, the node itself is undone by `evaluate`
+                    for instance in nodes[..modules.len()].iter() {
+                        self.context.forget_prototype(node_name(instance), None);
+                    }
+                    return Err(message);
+                }
+            }
+        }
+
+        for (node, (module, call)) in nodes.iter().zip(modules) {
+            self.run(node, module, call, output)?;
+        }
+        Ok(())
+    }
+
+    /// Generates the module of `node` and checks that the engine takes it. An anonymous
+    /// expression also gives the name and return type of the function to call.
+    fn build(&mut self, node: &ASTNode, output: &mut Vec<String>) -> Result<(Module, Option<(String, Type)>), String> {
+        if self.show_ast {
+            output.push(format!("{:#?}", node));
+        }
+
+        let name = node_name(node);
+        let anonymous = name.is_empty();
+
+        let module = self.context.new_module(if anonymous { ANONYMOUS_FUNCTION_NAME } else { name });
+        let function = node.codegen(&mut self.context, &module)?;
+
+        // MCJIT keeps resolving a symbol to its first definition even after the module
+        // is removed, so every anonymous function gets a name of its own
+        let anonymous_name = format!("{}.{}", ANONYMOUS_FUNCTION_NAME, self.evaluated);
+        if anonymous {
+            self.evaluated += 1;
+            unsafe {
+                LLVMSetValueName2(function, anonymous_name.as_ptr() as *const c_char, anonymous_name.len());
+            }
+        }
+
+        if self.opt_level != O0 {
+            PassPipeline::preset(self.opt_level).run(&module, None)?;
+        }
+
+        if self.show_ir {
+            output.push(module.to_string());
+        }
+
+        match self.backend {
+            Backend::Mcjit(ref engine) => engine.verify_host_functions(&module)?,
+            Backend::Orc(ref engine) => engine.verify_module(&module)?
+        }
+
+        let call = match *node {
+            FunctionsNode(ref function) if anonymous => Some((anonymous_name, function.prototype.ret_type.unwrap_or(F64))),
+            _ => None
+        };
+        Ok((module, call))
+    }
+
+    /// Hands a module `build` made to the engine and calls its anonymous function.
+    fn run(&mut self, node: &ASTNode, module: Module, call: Option<(String, Type)>, output: &mut Vec<String>) -> Result<(), String> {
+        match self.backend {
+            Backend::Mcjit(ref engine) => {
+                let module = engine.add_module(module);
+                if let Some((anonymous_name, ret)) = call {
+                    let result = call_anonymous(engine, &anonymous_name, ret);
+                    // nothing else calls an anonymous function
+                    unsafe {
+                        engine.remove_module(module)?;
+                    }
+                    output.push(result?);
+                }
+            },
+            Backend::Orc(ref mut engine) => {
+                engine.add_module(module)?;
+                if let Some((anonymous_name, ret)) = call {
+                    let result = call_anonymous(engine, &anonymous_name, ret);
+                    // nothing else calls an anonymous function
+                    unsafe {
+                        engine.remove_function(&anonymous_name)?;
+                    }
+                    output.push(result?);
+                }
+            }
+        }
+
+        if let FunctionsNode(ref function) = *node {
+            if !function.prototype.name.is_empty() {
+                self.defined.insert(function.prototype.name.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `expr` ends at `end` with an if that has no else.
+fn awaits_else(expr: &Expression, end: usize) -> bool {
+    if expr.span.end.offset != end {
+        return false;
+    }
+    match expr.kind {
+        ConditionalExpr { else_expr: None, .. } => true,
+        ConditionalExpr { else_expr: Some(ref last), .. } | UnaryExpr(_, ref last) | BinaryExpr(_, _, ref last) |
+        LoopExpr { body_expr: ref last, .. } | BreakExpr { value: Some(ref last), .. } | ReturnExpr(ref last) |
+        VarExpr { body_expr: ref last, .. } | AssignExpr(_, ref last) => awaits_else(last, end),
+        _ => false
+    }
+}
+
+fn node_name(node: &ASTNode) -> &str {
+    match *node {
+        ExternNode(ref prototype) => &prototype.name,
+        FunctionsNode(ref function) => &function.prototype.name
+    }
+}
\ No newline at end of file
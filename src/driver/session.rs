@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use crate::lexer::tokens::try_tokenize;
+use crate::ast::ast::{default_parser_settings, parse_recovering, ASTNode, ParserSettings};
+use crate::ast::diagnostic::Diagnostic;
+use crate::typeck::checker::TypeChecker;
+#[cfg(feature = "llvm")]
+use crate::ast::ast::FunctionsNode;
+#[cfg(feature = "llvm")]
+use crate::codegen::builder::{build_module, Context, ANONYMOUS_FUNCTION_NAME};
+#[cfg(feature = "llvm")]
+use crate::codegen::jitter::{initialize_native_target, link_in_mcjit, MCJITBuilder};
+#[cfg(feature = "llvm")]
+use crate::codegen::module::Module;
+#[cfg(feature = "llvm")]
+use crate::driver::repl::call_anonymous;
+
+/// Index of a file added to a `Session`.
+pub type FileId = usize;
+
+pub struct SourceFile {
+    pub name: String,
+    pub source: String
+}
+
+/// Everything needed to compile a program made of one or more source files. Files are
+/// parsed with shared settings, so operators defined in one can be used by the next, and
+/// checked and compiled against everything the session has seen before.
+///
+/// Diagnostics are collected instead of stopping at the first one, `report` renders
+/// them for a terminal.
+pub struct Session {
+    files: Vec<SourceFile>,
+    settings: ParserSettings,
+    checker: TypeChecker,
+    diagnostics: Vec<(FileId, Diagnostic)>,
+    /// The tree of every file that passed `check`.
+    checked: HashMap<FileId, Vec<ASTNode>>,
+    #[cfg(feature = "llvm")]
+    context: Context,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            files: Vec::new(),
+            settings: default_parser_settings(),
+            checker: TypeChecker::new(),
+            diagnostics: Vec::new(),
+            checked: HashMap::new(),
+            #[cfg(feature = "llvm")]
+            context: Context::new(),
+        }
+    }
+
+    /// Parses with `settings` instead of the default operator precedences.
+    pub fn with_settings(mut self, settings: ParserSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn add_file(&mut self, name: &str, source: &str) -> FileId {
+        self.files.push(SourceFile { name: name.to_string(), source: source.to_string() });
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, file: FileId) -> &SourceFile {
+        &self.files[file]
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn diagnostics(&self) -> &[(FileId, Diagnostic)] {
+        &self.diagnostics
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|(_, diagnostic)| diagnostic.is_error())
+    }
+
+    /// Lexes and parses `file`. Only lexical errors fail right away, after syntax errors
+    /// the recovered tree is still returned so that `check` can report its errors too.
+    pub fn parse(&mut self, file: FileId) -> Result<Vec<ASTNode>, String> {
+        let tokens = match try_tokenize(&self.files[file].source) {
+            Ok(tokens) => tokens,
+            Err(errors) => {
+                self.record(file, errors.into_iter().map(Diagnostic::from).collect());
+                return Err(self.aborting());
+            }
+        };
+
+        let (ast, rest, mut diagnostics) = parse_recovering(&tokens, &[], &mut self.settings);
+        if let (Some(first), Some(last)) = (rest.first(), rest.last()) {
+            diagnostics.push(Diagnostic::error("unexpected end of input", first.span.to(last.span)));
+        }
+
+        self.record(file, diagnostics);
+        Ok(ast)
+    }
+
+    /// Type checks `ast`, parsed from `file`, and fills in the inferred types. Fails if
+    /// this or an earlier step found an error.
+    pub fn check(&mut self, file: FileId, ast: &mut Vec<ASTNode>) -> Result<(), String> {
+        let diagnostics = self.checker.check(ast);
+        self.record(file, diagnostics);
+
+        if self.has_errors() {
+            return Err(self.aborting());
+        }
+        self.checked.insert(file, ast.clone());
+        Ok(())
+    }
+
+    /// Renders every diagnostic with the line it points at.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        for (file, diagnostic) in self.diagnostics.iter() {
+            let file = &self.files[*file];
+            let line = file.source.lines().nth(diagnostic.span.start.line - 1).unwrap_or("");
+            let width = if diagnostic.span.start.line == diagnostic.span.end.line {
+                diagnostic.span.end.column.saturating_sub(diagnostic.span.start.column).max(1)
+            } else {
+                1
+            };
+
+            report.push_str(&format!("{}:{}\n", file.name, diagnostic));
+            report.push_str(&format!("    {}\n", line));
+            report.push_str(&format!("    {}{}\n", " ".repeat(diagnostic.span.start.column - 1), "^".repeat(width)));
+        }
+
+        report
+    }
+
+    fn record(&mut self, file: FileId, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics.extend(diagnostics.into_iter().map(|diagnostic| (file, diagnostic)));
+    }
+
+    fn aborting(&self) -> String {
+        let count = self.diagnostics.iter().filter(|(_, diagnostic)| diagnostic.is_error()).count();
+        format!("aborting due to {} previous error(s)", count)
+    }
+}
+
+#[cfg(feature = "llvm")]
+impl Session {
+    /// The LLVM context modules of this session are created in.
+    pub fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Generates an LLVM module named `name` from the checked `ast`.
+    pub fn compile_to_module(&mut self, name: &str, ast: &[ASTNode]) -> Result<Module, String> {
+        build_module(&mut self.context, name, ast)
+    }
+
+    /// Compiles every file into one module, runs it on the MCJIT and gives the value of
+    /// each top-level expression in order. Files that were checked already are not parsed
+    /// and checked again.
+    pub fn jit_eval(&mut self) -> Result<Vec<String>, String> {
+        if self.has_errors() {
+            return Err(self.aborting());
+        }
+
+        let mut program = Vec::new();
+        for file in 0..self.files.len() {
+            let ast = match self.checked.get(&file) {
+                Some(ast) => ast.clone(),
+                None => {
+                    let mut ast = self.parse(file)?;
+                    self.check(file, &mut ast)?;
+                    ast
+                }
+            };
+            program.extend(ast);
+        }
+
+        link_in_mcjit();
+        initialize_native_target();
+
+        let name = self.files.first().map(|file| file.name.clone()).unwrap_or_default();
+        let module = self.compile_to_module(&name, &program)?;
+        let anonymous: Vec<String> = module.defined_functions().into_iter()
+            .filter(|name| name.starts_with(ANONYMOUS_FUNCTION_NAME))
+            .collect();
+        let return_types = program.iter().filter_map(|node| match *node {
+            FunctionsNode(ref function) if function.prototype.name.is_empty() => function.prototype.ret_type,
+            _ => None
+        });
+
+        let engine = MCJITBuilder::new().create(module)?;
+        anonymous.iter().zip(return_types)
+            .map(|(name, ret)| call_anonymous(&engine, name, ret))
+            .collect()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
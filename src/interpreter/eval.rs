@@ -0,0 +1,419 @@
+use std::collections::HashMap;
+
+use crate::ast::ast::{
+    ASTNode,
+    Expression,
+    Functions,
+    Prototype,
+    ExternNode,
+    FunctionsNode,
+    LiteralExpr,
+    VariableExpr,
+    UnaryExpr,
+    BinaryExpr,
+    ConditionalExpr,
+    LoopExpr,
+    WhileExpr,
+    BreakExpr,
+    ContinueExpr,
+    ReturnExpr,
+    VarExpr,
+    AssignExpr,
+    BlockExpr,
+    CallExpr,
+    IntegerLiteral,
+};
+use crate::ast::types::{F64, Unit};
+use crate::typeck::checker::{COMPARISON_OPERATORS, LOGICAL_OPERATORS};
+use crate::interpreter::value::{Value, BoolValue, F64Value, UnitValue};
+
+/// A function the interpreter calls for an `extern` declaration.
+pub type HostFunction = Box<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+type UnaryMathFunction = fn(f64) -> f64;
+type BinaryMathFunction = fn(f64, f64) -> f64;
+
+/// The functions of the C math library a program can declare as `extern`, taking and
+/// returning `f64`.
+const MATH_FUNCTIONS: &[(&str, UnaryMathFunction)] = &[
+    ("sin", f64::sin),
+    ("cos", f64::cos),
+    ("tan", f64::tan),
+    ("asin", f64::asin),
+    ("acos", f64::acos),
+    ("atan", f64::atan),
+    ("sinh", f64::sinh),
+    ("cosh", f64::cosh),
+    ("tanh", f64::tanh),
+    ("exp", f64::exp),
+    ("exp2", f64::exp2),
+    ("log", f64::ln),
+    ("log2", f64::log2),
+    ("log10", f64::log10),
+    ("sqrt", f64::sqrt),
+    ("cbrt", f64::cbrt),
+    ("fabs", f64::abs),
+    ("floor", f64::floor),
+    ("ceil", f64::ceil),
+    ("round", f64::round),
+    ("trunc", f64::trunc),
+];
+
+const BINARY_MATH_FUNCTIONS: &[(&str, BinaryMathFunction)] = &[
+    ("pow", f64::powf),
+    ("atan2", f64::atan2),
+    ("hypot", f64::hypot),
+    ("fmod", |a, b| a % b),
+    ("fmin", f64::min),
+    ("fmax", f64::max),
+];
+
+/// Why evaluating an expression stopped without a value.
+enum Unwind {
+    Error(String),
+    /// `break` with its label and value, on the way to the loop it leaves.
+    Break(Option<String>, Option<Value>),
+    Continue(Option<String>),
+    /// `return` with its value, on the way out of the function body.
+    Return(Value)
+}
+
+impl Unwind {
+    /// The value of a function body left this way, or the error. The parser makes sure
+    /// `break` and `continue` do not get out of one.
+    fn into_result(self) -> Result<Value, String> {
+        match self {
+            Unwind::Error(message) => Err(message),
+            Unwind::Break(..) => Err("break outside of a loop".to_string()),
+            Unwind::Continue(_) => Err("continue outside of a loop".to_string()),
+            Unwind::Return(value) => Ok(value)
+        }
+    }
+}
+
+impl From<String> for Unwind {
+    fn from(message: String) -> Unwind {
+        Unwind::Error(message)
+    }
+}
+
+/// Variables visible while evaluating a function body, later bindings shadow earlier
+/// ones with the same name.
+struct Environment {
+    bindings: Vec<(String, Value)>
+}
+
+impl Environment {
+    fn get(&self, name: &str) -> Option<Value> {
+        self.bindings.iter().rev().find(|binding| binding.0 == name).map(|binding| binding.1)
+    }
+
+    /// Changes the value of the innermost variable called `name`.
+    fn set(&mut self, name: &str, value: Value) -> bool {
+        match self.bindings.iter_mut().rev().find(|binding| binding.0 == name) {
+            Some(binding) => {
+                binding.1 = value;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn push(&mut self, name: &str, value: Value) {
+        self.bindings.push((name.to_string(), value));
+    }
+
+    fn pop(&mut self, count: usize) {
+        let len = self.bindings.len();
+        self.bindings.truncate(len - count);
+    }
+}
+
+/// Evaluates the AST directly, without LLVM. It follows the semantics of the code the
+/// LLVM backend generates, so the two can be tested against each other, and runs typed
+/// programs the way the type checker left them; unchecked literals are `f64`.
+///
+/// Externs are served by host functions, the C math functions taking `f64` are there
+/// from the start.
+pub struct Interpreter {
+    functions: HashMap<String, Functions>,
+    externs: HashMap<String, Prototype>,
+    host_functions: HashMap<String, HostFunction>,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let mut interpreter = Interpreter {
+            functions: HashMap::new(),
+            externs: HashMap::new(),
+            host_functions: HashMap::new(),
+        };
+
+        for &(name, function) in MATH_FUNCTIONS {
+            interpreter.add_host_function(name, move |args| match *args {
+                [F64Value(x)] => Ok(F64Value(function(x))),
+                _ => Err(format!("{} takes one f64", name))
+            });
+        }
+        for &(name, function) in BINARY_MATH_FUNCTIONS {
+            interpreter.add_host_function(name, move |args| match *args {
+                [F64Value(x), F64Value(y)] => Ok(F64Value(function(x, y))),
+                _ => Err(format!("{} takes two f64", name))
+            });
+        }
+
+        interpreter
+    }
+
+    /// Makes calls to the external function `name` go to `function`.
+    pub fn add_host_function<F>(&mut self, name: &str, function: F)
+    where F: Fn(&[Value]) -> Result<Value, String> + 'static {
+        self.host_functions.insert(name.to_string(), Box::new(function));
+    }
+
+    /// Runs `ast` in order: externs and functions are defined and top-level expressions
+    /// evaluated, giving their values.
+    pub fn run(&mut self, ast: &[ASTNode]) -> Result<Vec<Value>, String> {
+        let mut values = Vec::new();
+
+        for node in ast.iter() {
+            match *node {
+                ExternNode(ref prototype) => {
+                    self.externs.insert(prototype.name.clone(), prototype.clone());
+                },
+                FunctionsNode(ref function) if function.prototype.name.is_empty() => {
+                    let mut environment = Environment { bindings: Vec::new() };
+                    values.push(self.eval(&function.body, &mut environment).or_else(Unwind::into_result)?);
+                },
+                FunctionsNode(ref function) => {
+                    self.functions.insert(function.prototype.name.clone(), function.clone());
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Calls the function or extern `name`.
+    pub fn call(&self, name: &str, args: &[Value]) -> Result<Value, String> {
+        if let Some(function) = self.functions.get(name) {
+            let prototype = &function.prototype;
+            if prototype.args.len() != args.len() {
+                return Err(format!("{}: incorrect number of arguments passed to {}", prototype.span, name));
+            }
+
+            let mut environment = Environment { bindings: Vec::new() };
+            for ((arg, _), value) in prototype.args.iter().zip(args.iter()) {
+                environment.push(arg, *value);
+            }
+            return self.eval(&function.body, &mut environment).or_else(Unwind::into_result);
+        }
+
+        match (self.externs.get(name), self.host_functions.get(name)) {
+            (Some(prototype), Some(function)) => {
+                if prototype.args.len() != args.len() {
+                    return Err(format!("{}: incorrect number of arguments passed to {}", prototype.span, name));
+                }
+                function(args).map_err(|message| format!("{}: {}", prototype.span, message))
+            },
+            (Some(prototype), None) => Err(format!("{}: extern {} is not available in the interpreter", prototype.span, name)),
+            (None, _) => Err(format!("unknown function referenced {}", name))
+        }
+    }
+
+    fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name) || self.externs.contains_key(name)
+    }
+
+    /// Runs the body of the loop labeled `label` once. Gives the value the loop is left
+    /// with if `break` leaves it, `zero` when `break` has no value.
+    fn run_loop_body(&self, body: &Expression, environment: &mut Environment, label: &Option<String>, zero: Value)
+    -> Result<Option<Value>, Unwind> {
+        match self.eval(body, environment) {
+            Ok(_) => Ok(None),
+            Err(Unwind::Continue(ref target)) if target.is_none() || target == label => Ok(None),
+            Err(Unwind::Break(ref target, value)) if target.is_none() || target == label => Ok(Some(value.unwrap_or(zero))),
+            Err(unwind) => Err(unwind)
+        }
+    }
+
+    fn eval(&self, expr: &Expression, environment: &mut Environment) -> Result<Value, Unwind> {
+        let error = |message: String| Unwind::Error(format!("{}: {}", expr.span, message));
+
+        match expr.kind {
+            LiteralExpr(ref literal) => Ok(Value::from_literal(literal, expr.ty.unwrap_or(F64))),
+
+            VariableExpr(ref name) => environment.get(name)
+                .ok_or_else(|| error(format!("unknown variable name {}", name))),
+
+            UnaryExpr(ref operator, ref operand) => {
+                let operand = self.eval(operand, environment)?;
+
+                let name = "unary".to_string() + operator;
+                if self.has_function(&name) {
+                    return self.call(&name, &[operand]).map_err(Unwind::from);
+                }
+                match operator.as_str() {
+                    "-" => operand.wrapping_neg().map_err(error),
+                    "~" => operand.bit_not().map_err(error),
+                    "!" => Ok(BoolValue(!operand.is_true())),
+                    _ => Err(error(format!("unknown unary operator {}", operator)))
+                }
+            },
+
+            BinaryExpr(ref operator, ref lhs, ref rhs) if LOGICAL_OPERATORS.contains(&operator.as_str()) => {
+                let lhs = self.eval(lhs, environment)?.is_true();
+                if lhs == (operator == "||") {
+                    return Ok(BoolValue(lhs));
+                }
+                Ok(BoolValue(self.eval(rhs, environment)?.is_true()))
+            },
+
+            BinaryExpr(ref operator, ref lhs, ref rhs) => {
+                let lhs = self.eval(lhs, environment)?;
+                let rhs = self.eval(rhs, environment)?;
+
+                match operator.as_str() {
+                    "+" => lhs.wrapping_add(rhs).map_err(error),
+                    "-" => lhs.wrapping_sub(rhs).map_err(error),
+                    "*" => lhs.wrapping_mul(rhs).map_err(error),
+                    "/" => lhs.divide(rhs).map_err(error),
+                    "%" => lhs.remainder(rhs).map_err(error),
+                    "&" => lhs.bit_and(rhs).map_err(error),
+                    "|" => lhs.bit_or(rhs).map_err(error),
+                    "^" => lhs.bit_xor(rhs).map_err(error),
+                    "<<" => lhs.shift_left(rhs).map_err(error),
+                    ">>" => lhs.shift_right(rhs).map_err(error),
+                    op if COMPARISON_OPERATORS.contains(&op) => lhs.compare(rhs, op).map_err(error),
+                    op => {
+                        let name = "binary".to_string() + op;
+                        if !self.has_function(&name) {
+                            return Err(error(format!("unknown binary operator {}", op)));
+                        }
+                        self.call(&name, &[lhs, rhs]).map_err(Unwind::from)
+                    }
+                }
+            },
+
+            ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+                let value = if self.eval(cond_expr, environment)?.is_true() {
+                    self.eval(then_expr, environment)?
+                } else {
+                    match *else_expr {
+                        Some(ref else_expr) => self.eval(else_expr, environment)?,
+                        None => Value::from_literal(&IntegerLiteral(0), expr.ty.unwrap_or(F64))
+                    }
+                };
+
+                if expr.ty == Some(Unit) { Ok(UnitValue) } else { Ok(value) }
+            },
+
+            // like the generated loop, the body runs before the end condition is first
+            // checked, and the condition sees the variable before the step is added
+            LoopExpr{ref label, ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+                let mut variable = self.eval(start_expr, environment)?;
+
+                loop {
+                    environment.push(var_name, variable);
+                    let result = self.run_loop_body(body_expr, environment, label, F64Value(0.0)).and_then(|left| {
+                        if left.is_some() {
+                            return Ok(None);
+                        }
+                        let step = self.eval(step_expr, environment)?;
+                        let next = variable.wrapping_add(step).map_err(error)?;
+                        Ok(Some((next, self.eval(end_expr, environment)?)))
+                    });
+                    environment.pop(1);
+
+                    match result? {
+                        Some((next, end)) if end.is_true() => variable = next,
+                        _ => break
+                    }
+                }
+
+                Ok(F64Value(0.0))
+            },
+
+            WhileExpr{ref label, ref cond_expr, ref body_expr} => {
+                let zero = Value::from_literal(&IntegerLiteral(0), expr.ty.unwrap_or(F64));
+
+                loop {
+                    if let Some(ref cond_expr) = *cond_expr {
+                        if !self.eval(cond_expr, environment)?.is_true() {
+                            return Ok(zero);
+                        }
+                    }
+                    if let Some(value) = self.run_loop_body(body_expr, environment, label, zero)? {
+                        return Ok(value);
+                    }
+                }
+            },
+
+            BreakExpr{ref label, ref value} => {
+                let value = match *value {
+                    Some(ref value) => Some(self.eval(value, environment)?),
+                    None => None
+                };
+                Err(Unwind::Break(label.clone(), value))
+            },
+
+            ContinueExpr(ref label) => Err(Unwind::Continue(label.clone())),
+
+            ReturnExpr(ref value) => Err(Unwind::Return(self.eval(value, environment)?)),
+
+            VarExpr{ref vars, ref body_expr} => {
+                let mut bound = 0;
+                let mut result = Ok(F64Value(0.0));
+
+                for (name, _, init_expr, _) in vars.iter() {
+                    result = self.eval(init_expr, environment);
+                    match result {
+                        Ok(value) => environment.push(name, value),
+                        Err(_) => break
+                    }
+                    bound += 1;
+                }
+
+                if result.is_ok() {
+                    result = self.eval(body_expr, environment);
+                }
+                environment.pop(bound);
+                result
+            },
+
+            AssignExpr(ref name, ref value) => {
+                let value = self.eval(value, environment)?;
+                if !environment.set(name, value) {
+                    return Err(error(format!("unknown variable name {}", name)));
+                }
+                Ok(value)
+            },
+
+            BlockExpr(ref exprs) => {
+                let mut value = F64Value(0.0);
+                for expr in exprs.iter() {
+                    value = self.eval(expr, environment)?;
+                }
+                Ok(value)
+            },
+
+            CallExpr(ref name, ref args) => {
+                if !self.has_function(name) {
+                    return Err(error(format!("unknown function referenced {}", name)));
+                }
+
+                let mut values = Vec::new();
+                for arg in args.iter() {
+                    values.push(self.eval(arg, environment)?);
+                }
+                self.call(name, &values).map_err(Unwind::from)
+            }
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,2 @@
+pub mod eval;
+pub mod value;
\ No newline at end of file
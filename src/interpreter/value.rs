@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::ast::ast::{Literal, IntegerLiteral, FloatLiteral, BoolLiteral};
+use crate::ast::types::{Type, Bool, I32, I64, U32, U64, F32, F64, Unit};
+
+pub use self::Value::{
+    BoolValue,
+    I32Value,
+    I64Value,
+    U32Value,
+    U64Value,
+    F32Value,
+    F64Value,
+    UnitValue
+};
+
+/// A value of one of the Tobichi types. Integers wrap around like the machine code the
+/// LLVM backend generates, and values print like the results of the JIT.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Value {
+    BoolValue(bool),
+    I32Value(i32),
+    I64Value(i64),
+    U32Value(u32),
+    U64Value(u64),
+    F32Value(f32),
+    F64Value(f64),
+    UnitValue
+}
+
+impl Value {
+    /// The constant `literal` has when it is of type `ty`.
+    pub fn from_literal(literal: &Literal, ty: Type) -> Value {
+        match *literal {
+            IntegerLiteral(value) => match ty {
+                I32 => I32Value(value as i32),
+                I64 => I64Value(value),
+                U32 => U32Value(value as u32),
+                U64 => U64Value(value as u64),
+                F32 => F32Value(value as f32),
+                Bool => BoolValue(value & 1 == 1),
+                F64 => F64Value(value as f64),
+                Unit => UnitValue
+            },
+            FloatLiteral(value) if ty == F32 => F32Value(value as f32),
+            FloatLiteral(value) => F64Value(value),
+            BoolLiteral(value) => BoolValue(value)
+        }
+    }
+
+    pub fn ty(&self) -> Type {
+        match *self {
+            BoolValue(_) => Bool,
+            I32Value(_) => I32,
+            I64Value(_) => I64,
+            U32Value(_) => U32,
+            U64Value(_) => U64,
+            F32Value(_) => F32,
+            F64Value(_) => F64,
+            UnitValue => Unit
+        }
+    }
+
+    /// Whether a condition holds, numbers are true when they are not zero and NaN is
+    /// false.
+    pub fn is_true(&self) -> bool {
+        match *self {
+            BoolValue(value) => value,
+            I32Value(value) => value != 0,
+            I64Value(value) => value != 0,
+            U32Value(value) => value != 0,
+            U64Value(value) => value != 0,
+            F32Value(value) => value != 0.0 && !value.is_nan(),
+            F64Value(value) => value != 0.0 && !value.is_nan(),
+            UnitValue => false
+        }
+    }
+
+    pub fn wrapping_add(self, other: Value) -> Result<Value, String> {
+        self.arithmetic(other, "+", i64::wrapping_add, |a, b| a + b)
+    }
+
+    pub fn wrapping_sub(self, other: Value) -> Result<Value, String> {
+        self.arithmetic(other, "-", i64::wrapping_sub, |a, b| a - b)
+    }
+
+    pub fn wrapping_mul(self, other: Value) -> Result<Value, String> {
+        self.arithmetic(other, "*", i64::wrapping_mul, |a, b| a * b)
+    }
+
+    /// `/`, integer division rounds toward zero. Dividing an integer by zero is an error
+    /// here, the compiled code has no defined result for it.
+    pub fn divide(self, other: Value) -> Result<Value, String> {
+        match (self, other) {
+            (I32Value(_), I32Value(0)) | (I64Value(_), I64Value(0)) |
+            (U32Value(_), U32Value(0)) | (U64Value(_), U64Value(0)) => Err("division by zero".to_string()),
+            (I32Value(a), I32Value(b)) => Ok(I32Value(a.wrapping_div(b))),
+            (I64Value(a), I64Value(b)) => Ok(I64Value(a.wrapping_div(b))),
+            (U32Value(a), U32Value(b)) => Ok(U32Value(a / b)),
+            (U64Value(a), U64Value(b)) => Ok(U64Value(a / b)),
+            (F32Value(a), F32Value(b)) => Ok(F32Value(a / b)),
+            (F64Value(a), F64Value(b)) => Ok(F64Value(a / b)),
+            _ => Err(self.mismatch(other, "/"))
+        }
+    }
+
+    /// `%`, the remainder takes the sign of the dividend like C's `fmod` and `%`.
+    pub fn remainder(self, other: Value) -> Result<Value, String> {
+        match (self, other) {
+            (I32Value(_), I32Value(0)) | (I64Value(_), I64Value(0)) |
+            (U32Value(_), U32Value(0)) | (U64Value(_), U64Value(0)) => Err("division by zero".to_string()),
+            (I32Value(a), I32Value(b)) => Ok(I32Value(a.wrapping_rem(b))),
+            (I64Value(a), I64Value(b)) => Ok(I64Value(a.wrapping_rem(b))),
+            (U32Value(a), U32Value(b)) => Ok(U32Value(a % b)),
+            (U64Value(a), U64Value(b)) => Ok(U64Value(a % b)),
+            (F32Value(a), F32Value(b)) => Ok(F32Value(a % b)),
+            (F64Value(a), F64Value(b)) => Ok(F64Value(a % b)),
+            _ => Err(self.mismatch(other, "%"))
+        }
+    }
+
+    pub fn bit_and(self, other: Value) -> Result<Value, String> {
+        self.bitwise(other, "&", |a, b| a & b)
+    }
+
+    pub fn bit_or(self, other: Value) -> Result<Value, String> {
+        self.bitwise(other, "|", |a, b| a | b)
+    }
+
+    pub fn bit_xor(self, other: Value) -> Result<Value, String> {
+        self.bitwise(other, "^", |a, b| a ^ b)
+    }
+
+    /// `<<`, the shift amount is taken modulo the width of the type.
+    pub fn shift_left(self, other: Value) -> Result<Value, String> {
+        match (self, other) {
+            (I32Value(a), I32Value(b)) => Ok(I32Value(a.wrapping_shl(b as u32))),
+            (I64Value(a), I64Value(b)) => Ok(I64Value(a.wrapping_shl(b as u32))),
+            (U32Value(a), U32Value(b)) => Ok(U32Value(a.wrapping_shl(b))),
+            (U64Value(a), U64Value(b)) => Ok(U64Value(a.wrapping_shl(b as u32))),
+            _ => Err(self.mismatch(other, "<<"))
+        }
+    }
+
+    /// `>>`, which shifts in the sign bit for signed types and zeros otherwise.
+    pub fn shift_right(self, other: Value) -> Result<Value, String> {
+        match (self, other) {
+            (I32Value(a), I32Value(b)) => Ok(I32Value(a.wrapping_shr(b as u32))),
+            (I64Value(a), I64Value(b)) => Ok(I64Value(a.wrapping_shr(b as u32))),
+            (U32Value(a), U32Value(b)) => Ok(U32Value(a.wrapping_shr(b))),
+            (U64Value(a), U64Value(b)) => Ok(U64Value(a.wrapping_shr(b as u32))),
+            _ => Err(self.mismatch(other, ">>"))
+        }
+    }
+
+    /// One of the comparison operators. Like in the LLVM backend ordering comparisons are
+    /// true when a float is NaN and `==` is false, and bools can only be tested for
+    /// equality.
+    pub fn compare(self, other: Value, operator: &str) -> Result<Value, String> {
+        let ordering = match (self, other) {
+            (BoolValue(a), BoolValue(b)) if operator == "==" || operator == "!=" => a.partial_cmp(&b),
+            (I32Value(a), I32Value(b)) => a.partial_cmp(&b),
+            (I64Value(a), I64Value(b)) => a.partial_cmp(&b),
+            (U32Value(a), U32Value(b)) => a.partial_cmp(&b),
+            (U64Value(a), U64Value(b)) => a.partial_cmp(&b),
+            (F32Value(a), F32Value(b)) => a.partial_cmp(&b),
+            (F64Value(a), F64Value(b)) => a.partial_cmp(&b),
+            _ => return Err(self.mismatch(other, operator))
+        };
+
+        let result = match operator {
+            "<" => !matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            "<=" => ordering != Some(Ordering::Greater),
+            ">" => !matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            ">=" => ordering != Some(Ordering::Less),
+            "==" => ordering == Some(Ordering::Equal),
+            "!=" => ordering != Some(Ordering::Equal),
+            _ => return Err(format!("unknown comparison operator {}", operator))
+        };
+        Ok(BoolValue(result))
+    }
+
+    pub fn wrapping_neg(self) -> Result<Value, String> {
+        match self {
+            I32Value(value) => Ok(I32Value(value.wrapping_neg())),
+            I64Value(value) => Ok(I64Value(value.wrapping_neg())),
+            U32Value(value) => Ok(U32Value(value.wrapping_neg())),
+            U64Value(value) => Ok(U64Value(value.wrapping_neg())),
+            F32Value(value) => Ok(F32Value(-value)),
+            F64Value(value) => Ok(F64Value(-value)),
+            value => Err(format!("cannot apply unary operator - to {}", value.ty()))
+        }
+    }
+
+    /// `~`, flipping every bit of an integer.
+    pub fn bit_not(self) -> Result<Value, String> {
+        match self {
+            I32Value(value) => Ok(I32Value(!value)),
+            I64Value(value) => Ok(I64Value(!value)),
+            U32Value(value) => Ok(U32Value(!value)),
+            U64Value(value) => Ok(U64Value(!value)),
+            value => Err(format!("cannot apply unary operator ~ to {}", value.ty()))
+        }
+    }
+
+    /// Applies an operator working the same on all integers, the result is truncated to
+    /// the width of the operands. Two's complement makes that right for both signs.
+    fn arithmetic<I, F>(self, other: Value, operator: &str, int: I, float: F) -> Result<Value, String>
+    where I: Fn(i64, i64) -> i64, F: Fn(f64, f64) -> f64 {
+        match (self, other) {
+            (I32Value(a), I32Value(b)) => Ok(I32Value(int(a as i64, b as i64) as i32)),
+            (I64Value(a), I64Value(b)) => Ok(I64Value(int(a, b))),
+            (U32Value(a), U32Value(b)) => Ok(U32Value(int(a as i64, b as i64) as u32)),
+            (U64Value(a), U64Value(b)) => Ok(U64Value(int(a as i64, b as i64) as u64)),
+            (F32Value(a), F32Value(b)) => Ok(F32Value(float(a as f64, b as f64) as f32)),
+            (F64Value(a), F64Value(b)) => Ok(F64Value(float(a, b))),
+            _ => Err(self.mismatch(other, operator))
+        }
+    }
+
+    /// Like `arithmetic`, for operators only defined on integers.
+    fn bitwise<I>(self, other: Value, operator: &str, int: I) -> Result<Value, String>
+    where I: Fn(i64, i64) -> i64 {
+        match (self, other) {
+            (I32Value(a), I32Value(b)) => Ok(I32Value(int(a as i64, b as i64) as i32)),
+            (I64Value(a), I64Value(b)) => Ok(I64Value(int(a, b))),
+            (U32Value(a), U32Value(b)) => Ok(U32Value(int(a as i64, b as i64) as u32)),
+            (U64Value(a), U64Value(b)) => Ok(U64Value(int(a as i64, b as i64) as u64)),
+            _ => Err(self.mismatch(other, operator))
+        }
+    }
+
+    fn mismatch(&self, other: Value, operator: &str) -> String {
+        format!("cannot apply binary operator {} to {} and {}", operator, self.ty(), other.ty())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoolValue(value) => write!(f, "{}", value),
+            I32Value(value) => write!(f, "{}", value),
+            I64Value(value) => write!(f, "{}", value),
+            U32Value(value) => write!(f, "{}", value),
+            U64Value(value) => write!(f, "{}", value),
+            F32Value(value) => write!(f, "{}", value),
+            F64Value(value) => write!(f, "{}", value),
+            UnitValue => write!(f, "()")
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,37 @@
+use std::fmt;
+use crate::lexer::span::Span;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum LexErrorKind {
+    MalformedNumber(String),
+    UnterminatedBlockComment,
+    InvalidCharacter(char)
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span
+}
+
+impl LexError {
+    pub fn new(kind: LexErrorKind, span: Span) -> LexError {
+        LexError { kind, span }
+    }
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexErrorKind::MalformedNumber(ref number) => write!(f, "malformed number literal `{}`", number),
+            LexErrorKind::UnterminatedBlockComment => write!(f, "unterminated block comment"),
+            LexErrorKind::InvalidCharacter(c) => write!(f, "invalid character `{}`", c.escape_default())
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
\ No newline at end of file
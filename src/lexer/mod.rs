@@ -1 +1,3 @@
+pub mod error;
+pub mod span;
 pub mod tokens;
\ No newline at end of file
@@ -1,3 +1,7 @@
+use crate::lexer::error::LexError;
+use crate::lexer::error::LexErrorKind::{MalformedNumber, UnterminatedBlockComment, InvalidCharacter};
+use crate::lexer::span::{Position, Span};
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum Tokens {
     Function,
@@ -6,8 +10,14 @@ pub enum Tokens {
     Then,
     Else,
     For,
+    While,
+    Loop,
+    Break,
+    Continue,
+    Return,
     In,
     Let,
+    Mut,
     Binary,
     Unary,
     Delimiter,
@@ -16,8 +26,14 @@ pub enum Tokens {
     OpeningBrackets,
     ClosingBrackets,
     Comma,
+    Colon,
+    Arrow,
+    /// A loop label like `'dis`, without the quote.
+    Label(String),
     Ident(String),
+    Integer(i64),
     Number(f64),
+    Bool(bool),
     Operator(String)
 }
 
@@ -28,8 +44,14 @@ pub use self::Tokens::{
     Then,
     Else,
     For,
+    While,
+    Loop,
+    Break,
+    Continue,
+    Return,
     In,
     Let,
+    Mut,
     Binary,
     Unary,
     Delimiter,
@@ -38,31 +60,79 @@ pub use self::Tokens::{
     OpeningBrackets,
     ClosingBrackets,
     Comma,
+    Colon,
+    Arrow,
+    Label,
     Ident,
+    Integer,
     Number,
+    Bool,
     Operator
 };
 
-pub fn tokenize(input: &str) -> Vec<Tokens> {
-    let comment_re = regex::Regex::new(r"//[^\n]*|/\*[^*]*\*+(?:[^/*][^*]*\*+)*/").unwrap();
-    let preprocessed = comment_re.replace_all(input, "");
+#[derive(PartialEq, Clone, Debug)]
+pub struct Token {
+    pub kind: Tokens,
+    pub span: Span
+}
+
+impl Token {
+    pub fn new(kind: Tokens, span: Span) -> Token {
+        Token { kind, span }
+    }
+}
+
+/// Lexes `input`, panicking on the first lexical error. Use `try_tokenize` for input
+/// that is not known to be valid.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    match try_tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(errors) => panic!("Error: {}", errors[0])
+    }
+}
 
+/// Lexes `input`, skipping over anything malformed so that every lexical error in the
+/// source is reported at once.
+pub fn try_tokenize(input: &str) -> Result<Vec<Token>, Vec<LexError>> {
     let tokens = regex::Regex::new(concat!(
+        r"(?P<comment>//[^\n]*|/\*[^*]*\*+(?:[^/*][^*]*\*+)*/)|",
+        r"(?P<unterminated>/\*)|",
         r"(?P<ident>\p{Alphabetic}\w*)|",
-        r"(?P<number>\d+\.?\d*)|",
+        r"(?P<number>\d[\w.]*)|",
         r"(?P<delimiter>;)|",
         r"(?P<oppar>\()|",
         r"(?P<clpar>\))|",
         r"(?P<obrack>\{)|",
         r"(?P<cbrack>\})|",
         r"(?P<comma>,)|",
-        r"(?P<operator>\S)",
+        r"(?P<colon>:)|",
+        r"(?P<arrow>->)|",
+        r"(?P<label>'\p{Alphabetic}\w*)|",
+        r"(?P<operator>==|!=|<=|>=|<<|>>|&&|\|\||[[:punct:]])|",
+        r"(?P<invalid>\S)",
     )).unwrap();
+    let integer_re = regex::Regex::new(r"^\d+$").unwrap();
+    let float_re = regex::Regex::new(r"^\d+\.\d*$").unwrap();
 
     let mut lexed_input = Vec::new();
+    let mut errors = Vec::new();
+    let mut position = Position::start();
 
-    for capture in tokens.captures_iter(&preprocessed) {
-        let token = if let Some(ident) = capture.name("ident") {
+    for capture in tokens.captures_iter(input) {
+        let matched = capture.get(0).unwrap();
+        position.advance(&input[position.offset..matched.start()]);
+        let start = position;
+        position.advance(matched.as_str());
+        let span = Span::new(start, position);
+
+        let token = if capture.name("comment").is_some() {
+            continue
+        } else if capture.name("unterminated").is_some() {
+            // everything up to the end of the input belongs to the comment
+            position.advance(&input[position.offset..]);
+            errors.push(LexError::new(UnterminatedBlockComment, Span::new(start, position)));
+            break
+        } else if let Some(ident) = capture.name("ident") {
             match ident.as_str() {
                 "function" => Function,
                 "extern" => Extern,
@@ -70,16 +140,36 @@ pub fn tokenize(input: &str) -> Vec<Tokens> {
                 "then" => Then,
                 "else" => Else,
                 "for" => For,
+                "while" => While,
+                "loop" => Loop,
+                "break" => Break,
+                "continue" => Continue,
+                "return" => Return,
                 "in" => In,
                 "let" => Let,
+                "mut" => Mut,
                 "binary" => Binary,
                 "unary" => Unary,
+                "true" => Bool(true),
+                "false" => Bool(false),
                 ident => Ident(ident.to_string())
             }
         } else if let Some(number) = capture.name("number") {
-            match number.as_str().parse() {
-                Ok(num) => Number(num),
-                Err(_) => panic!("Error: An error occurred while parsing the number")
+            let number = number.as_str();
+            let token = if integer_re.is_match(number) {
+                number.parse().map(Integer).ok()
+            } else if float_re.is_match(number) {
+                number.parse().map(Number).ok()
+            } else {
+                None
+            };
+
+            match token {
+                Some(token) => token,
+                None => {
+                    errors.push(LexError::new(MalformedNumber(number.to_string()), span));
+                    continue
+                }
             }
         } 
         else if capture.name("delimiter").is_some() { Delimiter }
@@ -88,11 +178,22 @@ pub fn tokenize(input: &str) -> Vec<Tokens> {
         else if capture.name("obrack").is_some() { OpeningBrackets }
         else if capture.name("cbrack").is_some() { ClosingBrackets }
         else if capture.name("comma").is_some() { Comma }
+        else if capture.name("colon").is_some() { Colon }
+        else if capture.name("arrow").is_some() { Arrow }
+        else if let Some(label) = capture.name("label") { Label(label.as_str()[1..].to_string()) }
         else if let Some(op) = capture.name("operator") { Operator(op.as_str().to_string()) }
-        else { panic!("Error: Unknown token detected") };
+        else {
+            let c = matched.as_str().chars().next().unwrap();
+            errors.push(LexError::new(InvalidCharacter(c), span));
+            continue
+        };
 
-        lexed_input.push(token);
+        lexed_input.push(Token::new(token, span));
     }
 
-    lexed_input
+    if errors.is_empty() {
+        Ok(lexed_input)
+    } else {
+        Err(errors)
+    }
 }
\ No newline at end of file
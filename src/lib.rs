@@ -1,20 +1,58 @@
 #![recursion_limit = "256"]
 
-mod lexer;
-mod ast;
-mod codegen;
+pub mod lexer;
+pub mod ast;
+pub mod typeck;
+#[cfg(feature = "llvm")]
+pub mod codegen;
+pub mod driver;
+pub mod interpreter;
+
+pub use crate::lexer::tokens::{tokenize, try_tokenize, Token, Tokens};
+pub use crate::lexer::span::{Position, Span};
+pub use crate::lexer::error::{LexError, LexErrorKind};
+pub use crate::ast::ast::{
+    default_parser_settings,
+    parse,
+    parse_recovering,
+    ASTNode,
+    Expression,
+    ExpressionKind,
+    Functions,
+    FunctionType,
+    Literal,
+    ParserSettings,
+    Prototype,
+};
+pub use crate::ast::diagnostic::{Diagnostic, Severity};
+pub use crate::ast::types::Type;
+pub use crate::typeck::checker::{Signature, TypeChecker};
+pub use crate::interpreter::eval::Interpreter;
+pub use crate::interpreter::value::Value;
+pub use crate::driver::session::{FileId, Session, SourceFile};
+#[cfg(feature = "llvm")]
+pub use crate::codegen::builder::{build_module, Context};
+#[cfg(feature = "llvm")]
+pub use crate::codegen::jitter::{ExecutionEngine, HostFunction, Jit, MCJITBuilder};
+#[cfg(feature = "llvm")]
+pub use crate::codegen::module::Module;
+#[cfg(feature = "llvm")]
+pub use crate::codegen::orc::OrcEngine;
 
 #[cfg(test)]
 mod tests {
     use super::lexer::tokens::tokenize;
     use super::lexer::tokens::Tokens::*;
-    use super::ast::ast::{default_parser_settings, parse, ASTNode};
+    use super::ast::ast::{default_parser_settings, parse, ASTNode, FunctionsNode};
+    use super::typeck::checker::TypeChecker;
+    #[cfg(feature = "llvm")]
+    use super::codegen::jitter::Jit;
     
     #[test]
     fn test_tokenize() {
         let input = r#"
             let degisken1 = 1234 in
-            if x = 5 then y = 10 else y = 20
+            if x == 5 then 10 else 20
 
             function ornek_fonksiyon(x, y)
                 x + y;
@@ -36,4 +74,974 @@ mod tests {
             }
         }
     }
-}
+
+    #[test]
+    fn test_spans() {
+        let input = "function f(x)\n    /* yorum */ x $ 1;";
+        let tokens = tokenize(input);
+
+        assert_eq!(tokens[0].kind, Function);
+        assert_eq!((tokens[5].span.start.line, tokens[5].span.start.column), (2, 17));
+        assert_eq!(tokens[6].kind, Operator("$".to_string()));
+        assert_eq!(tokens[6].span.start.offset, 32);
+
+        let error = parse(&tokens, &[], &mut default_parser_settings()).unwrap_err();
+        assert_eq!(error, "2:19: unknown operator found");
+
+        let tokens = tokenize("function f(x) (x + 1) * 2;");
+        let (ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        match ast[0] {
+            FunctionsNode(ref function) => {
+                assert_eq!(function.prototype.span.len(), "f(x)".len());
+                assert_eq!(function.body.span.start.column, 15);
+                assert_eq!(function.body.span.len(), "(x + 1) * 2".len());
+            },
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        use super::lexer::error::LexErrorKind::*;
+        use super::lexer::tokens::try_tokenize;
+
+        let errors = try_tokenize("let a = 1.2.3 in a € 12ab\n/* yorum").unwrap_err();
+        let kinds: Vec<_> = errors.iter().map(|error| error.kind.clone()).collect();
+        assert_eq!(kinds, vec![
+            MalformedNumber("1.2.3".to_string()),
+            InvalidCharacter('€'),
+            MalformedNumber("12ab".to_string()),
+            UnterminatedBlockComment,
+        ]);
+        assert_eq!(errors[3].to_string(), "2:1: unterminated block comment");
+        assert_eq!(errors[3].span.len(), "/* yorum".len());
+
+        assert!(try_tokenize("let a = 1.5 in a /* yorum */").is_ok());
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        use super::ast::ast::parse_recovering;
+
+        let input = r#"
+            function f(x, 1) x;
+            function g(x) x + ) 1;
+            let a = 3 in a $ 2;
+            function h(x) x * 2;
+            extern sin(x);
+        "#;
+
+        let tokens = tokenize(input);
+        let (ast, rest, diagnostics) = parse_recovering(&tokens, &[], &mut default_parser_settings());
+
+        let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+        assert_eq!(messages, vec![
+            "2:27: error: expected ')' in prototype",
+            "3:31: error: Unexpected token: ClosingParenthesis when expecting an expression",
+            "4:28: error: unknown operator found",
+        ]);
+        assert_eq!(ast.len(), 2);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_repl() {
+        use super::codegen::jitter::HostFunction;
+        use super::driver::repl::{Repl, ReplStatus};
+
+        fn feed(repl: &mut Repl, line: &str) -> Vec<String> {
+            match repl.feed(line).unwrap() {
+                ReplStatus::Complete(output) => output,
+                ReplStatus::NeedMore => vec!["...".to_string()],
+                ReplStatus::Quit => vec![":quit".to_string()]
+            }
+        }
+
+        let mut repl = Repl::new().unwrap();
+        assert_eq!(feed(&mut repl, "function binary@ 50 (a, b)"), vec!["..."]);
+        assert_eq!(feed(&mut repl, "    a - b * 2;"), Vec::<String>::new());
+        assert_eq!(feed(&mut repl, "function kare(x) x * x;"), Vec::<String>::new());
+        assert_eq!(feed(&mut repl, "kare(4) @ 3; kare(2)"), vec!["10", "4"]);
+        assert!(repl.feed("function kare(x) x;").is_err());
+
+        feed(&mut repl, ":reset");
+        assert!(repl.feed("kare(2)").is_err());
+        assert!(repl.feed("1 @ 2").is_err());
+        assert_eq!(feed(&mut repl, "1 + 2"), vec!["3"]);
+
+        // an if without else at the end waits for an else on the next line
+        assert_eq!(feed(&mut repl, "function isaret(x: i64) -> i64 if x < 0 then 0 - 1"), vec!["..."]);
+        assert_eq!(feed(&mut repl, "    else if x == 0 then 0"), vec!["..."]);
+        assert_eq!(feed(&mut repl, "    else 1;"), Vec::<String>::new());
+        assert_eq!(feed(&mut repl, "isaret(0 - 5); let mut t = 0 in { if true then t = 1; t }"), vec!["-1", "1"]);
+        assert_eq!(feed(&mut repl, "isaret(7); if false then 1"), vec!["1"]);
+        assert_eq!(feed(&mut repl, "else 2"), vec!["2"]);
+        assert_eq!(feed(&mut repl, "if false then 1;"), vec!["()"]);
+
+        // nothing of an input that fails is kept
+        extern "C" fn yarim(x: f64) -> f64 {
+            x / 2.0
+        }
+        assert_eq!(feed(&mut repl, "extern yarim(x: i64) -> i64;"), Vec::<String>::new());
+        repl.add_host_function("yarim", HostFunction::new(yarim as extern "C" fn(f64) -> f64)).unwrap();
+        assert_eq!(
+            repl.feed("function ceyrek(x: i64) -> i64 yarim(yarim(x));").err(),
+            Some("extern yarim has type i64 (i64), but the host function has type double (double)".to_string())
+        );
+        assert_eq!(repl.feed("ceyrek(8)").err(), Some("1:1: unknown function referenced ceyrek".to_string()));
+        assert!(repl.feed("function binary# 5 (a: i64, b: i64) -> i64 a + true;").is_err());
+        assert_eq!(repl.feed("1 # 2").err(), Some("1:3: unknown operator found".to_string()));
+
+        // but what came before the failing node is
+        assert!(repl.feed("function binary# 5 (a: i64, b: i64) -> i64 a - b; 1 # true").is_err());
+        assert_eq!(feed(&mut repl, "4 # 1"), vec!["3"]);
+
+        // nor are the instances of generic functions it needed
+        assert_eq!(feed(&mut repl, "function kare(x) x * x;"), Vec::<String>::new());
+        assert!(repl.feed("function dort(x: i64) -> i64 kare(yarim(x));").is_err());
+        assert_eq!(feed(&mut repl, ":ir"), vec!["IR printing on"]);
+        let output = feed(&mut repl, "function dort(x: i64) -> i64 kare(x);");
+        assert!(output[0].contains("define i64 @kare.i64(i64 %x)"));
+        assert_eq!(feed(&mut repl, ":ir"), vec!["IR printing off"]);
+        assert_eq!(feed(&mut repl, "dort(3)"), vec!["9"]);
+    }
+
+    /// Type checks `input` and renders the diagnostics found.
+    fn check_messages(input: &str) -> Vec<String> {
+        let tokens = tokenize(input);
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        TypeChecker::new().check(&mut ast).iter().map(|diagnostic| diagnostic.to_string()).collect()
+    }
+
+    #[cfg(feature = "llvm")]
+    fn jit_engine(input: &str) -> super::codegen::jitter::ExecutionEngine {
+        use super::codegen::builder::{build_module, Context};
+        use super::codegen::jitter::{initialize_native_target, link_in_mcjit, MCJITBuilder};
+
+        link_in_mcjit();
+        initialize_native_target();
+
+        let tokens = tokenize(input);
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+        let mut context = Context::new();
+        let module = build_module(&mut context, "test", &ast).unwrap();
+        let engine = MCJITBuilder::new().create(module).unwrap();
+
+        // the engine keeps the LLVM context alive on its own
+        drop(context);
+        engine
+    }
+
+    #[cfg(feature = "llvm")]
+    fn jit_call(input: &str, name: &str, args: &[f64]) -> f64 {
+        let engine = jit_engine(input);
+        match args.len() {
+            1 => engine.get_function::<extern "C" fn(f64) -> f64>(name).unwrap().call((args[0],)),
+            2 => engine.get_function::<extern "C" fn(f64, f64) -> f64>(name).unwrap().call((args[0], args[1])),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_codegen() {
+        let input = r#"
+            function ornek_fonksiyon(x, y)
+                x + y * 2;
+
+            function binary@ 5 (lhs, rhs)
+                if lhs then 1 else if rhs then 1 else 0;
+
+            function fib(n)
+                if n < 3 then 1 else fib(n - 1) + fib(n - 2);
+
+            function toplam(n)
+                let acc = 0.0 in
+                    for i = 0.0, 1 i < n in acc + i;
+        "#;
+
+        assert_eq!(jit_call(input, "ornek_fonksiyon", &[12.0, 43.0]), 98.0);
+        assert_eq!(jit_call(input, "binary@", &[0.0, 3.0]), 1.0);
+        assert_eq!(jit_call(input, "fib", &[10.0]), 55.0);
+        assert_eq!(jit_call(input, "toplam", &[4.0]), 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_typed_codegen() {
+        let input = r#"
+            function kare(x: i64) -> i64
+                x * x - 1;
+
+            function kucuk(x: u32, y: u32) -> bool
+                x < y;
+
+            function yarim(x: f32) -> f32
+                if x < 1 then x else x * 0.5;
+        "#;
+
+        let engine = jit_engine(input);
+        let kare = engine.get_function::<extern "C" fn(i64) -> i64>("kare").unwrap();
+        assert_eq!(kare.call((-7,)), 48);
+
+        let kucuk = engine.get_function::<extern "C" fn(u32, u32) -> bool>("kucuk").unwrap();
+        assert!(kucuk.call((1, 4_000_000_000)));
+        assert!(!kucuk.call((4_000_000_000, 1)));
+
+        let yarim = engine.get_function::<extern "C" fn(f32) -> f32>("yarim").unwrap();
+        assert_eq!(yarim.call((3.0,)), 1.5);
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let input = r#"
+            function f(x: i32) -> bool x + 1;
+            function g(x: i64, y: f64) x + y;
+            function h(x) if x < 1 then x else true;
+            function k(b: bool) b * 2;
+            extern sin(x);
+            sin(true);
+            f(1);
+        "#;
+
+        assert_eq!(check_messages(input), vec![
+            "2:40: error: expected bool, found i32",
+            "3:44: error: expected i64, found f64",
+            "4:48: error: expected {number}, found bool",
+            "5:33: error: operator * is not defined for type bool",
+            "7:17: error: expected f64, found bool",
+            "8:13: error: unknown function referenced f",
+        ]);
+
+        // integer literals have to fit the type they end up with, negated ones included
+        let input = r#"
+            let x: i32 = 3000000000 in x;
+            function f() -> u32 -1;
+            function g(x: u32) x * 4294967296;
+            let y: i32 = -2147483648 in y;
+            let z: f32 = 3000000000 in z;
+            function h(x: u64) x - 9223372036854775807;
+        "#;
+
+        assert_eq!(check_messages(input), vec![
+            "2:26: error: literal 3000000000 is out of range for i32",
+            "3:33: error: literal -1 is out of range for u32",
+            "4:36: error: literal 4294967296 is out of range for u32",
+        ]);
+    }
+
+    #[test]
+    fn test_inference() {
+        let input = r#"
+            function ikiye(x) x * 2;
+
+            function sec(c, a: i32) if c then a else 0;
+
+            function say(n: i64)
+                let toplam = 0 in
+                    for i = 0, 1 i < n in ikiye(i) + toplam;
+
+            function cift(n: i32) ikiye(n) + ikiye(sec(1.5, 2));
+        "#;
+
+        let tokens = tokenize(input);
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        let mut checker = TypeChecker::new();
+        assert_eq!(checker.check(&mut ast), vec![]);
+
+        let names: Vec<&str> = ast.iter().map(|node| match *node {
+            FunctionsNode(ref function) => function.prototype.name.as_str(),
+            _ => unreachable!()
+        }).collect();
+        assert_eq!(names, vec!["ikiye", "sec", "ikiye.i64", "say", "ikiye.i32", "cift"]);
+
+        use super::ast::types::{I32, I64, F64};
+        let signature = |name| checker.signature(name).map(|signature| (signature.args, signature.ret));
+        assert_eq!(signature("ikiye"), Some((vec![F64], F64)));
+        assert_eq!(signature("sec"), Some((vec![F64, I32], I32)));
+        assert_eq!(signature("say"), Some((vec![I64], F64)));
+        assert_eq!(signature("cift"), Some((vec![I32], I32)));
+
+        let input_twice = "function g(x) x + 1; function f(x) g(x) * 2; function g(x: bool) -> bool x;";
+        assert_eq!(check_messages(input_twice), vec!["1:55: error: function `g` is already defined"]);
+
+        // where functions can be redefined, an instance that fails to check is not taken for made
+        let mut checker = TypeChecker::new().allow_redefinition();
+        let mut check = |input: &str| {
+            let tokens = tokenize(input);
+            let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+            checker.check(&mut ast).iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<_>>()
+        };
+        assert!(check(input_twice).is_empty());
+        assert_eq!(check("let a: i32 = 1 in f(a)"), vec!["1:38: error: expected bool, found i32"]);
+        assert_eq!(check("let a: i32 = 1 in f(a)"), vec!["1:38: error: expected bool, found i32"]);
+
+        #[cfg(feature = "llvm")]
+        {
+            let engine = jit_engine(input);
+            let cift = engine.get_function::<extern "C" fn(i32) -> i32>("cift").unwrap();
+            assert_eq!(cift.call((20,)), 44);
+            assert_eq!(jit_call(input, "ikiye", &[1.25]), 2.5);
+            assert_ne!(engine.get_function_address("ikiye.i64"), 0);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_emit_executable() {
+        use super::codegen::builder::{build_entry_point, build_module, Context};
+        use super::codegen::target::{write_bitcode_to_file, LLVMCodeGenFileType, TargetMachine};
+        use super::driver::link::{executable_name, link_executable};
+
+        let tokens = tokenize("function kare(x: i32) x * x; function main() kare(7) - 40;");
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+        let mut context = Context::new();
+        let module = build_module(&mut context, "test", &ast).unwrap();
+        let machine = TargetMachine::host().unwrap();
+        build_entry_point(&mut context, &module).unwrap();
+
+        let directory = std::env::temp_dir().join(format!("tobichi-emit-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).to_string_lossy().into_owned();
+
+        write_bitcode_to_file(&module, &path("kare.bc")).unwrap();
+        assert_eq!(&std::fs::read(path("kare.bc")).unwrap()[..2], b"BC");
+
+        machine.emit_to_file(&module, &path("kare.o"), LLVMCodeGenFileType::LLVMObjectFile).unwrap();
+        link_executable(&[&path("kare.o")], &path(&executable_name("kare")), None).unwrap();
+        let status = std::process::Command::new(path(&executable_name("kare"))).status().unwrap();
+        assert_eq!(status.code(), Some(9));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_optimize() {
+        use super::codegen::builder::{build_module, Context};
+        use super::codegen::optimize::{OptLevel, PassPipeline, O2};
+
+        let tokens = tokenize("function f(x: i64) let y = x * 1 in y + 0;");
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+        let mut context = Context::new();
+        let module = build_module(&mut context, "test", &ast).unwrap();
+        assert!(module.to_string().contains("mul i64"));
+
+        assert_eq!(OptLevel::from_name("2"), Some(O2));
+        assert!(PassPipeline::new("bilinmeyen").run(&module, None).is_err());
+        PassPipeline::new("function(instcombine)").verify_each().run(&module, None).unwrap();
+        PassPipeline::preset(O2).run(&module, None).unwrap();
+        assert!(module.to_string().contains("ret i64 %x"));
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_engine_ownership() {
+        use super::codegen::builder::{build_module, Context};
+        use super::codegen::jitter::{initialize_native_target, link_in_mcjit, MCJITBuilder};
+        use super::codegen::target::TargetMachine;
+
+        link_in_mcjit();
+        initialize_native_target();
+
+        let mut context = Context::new();
+        let engine = MCJITBuilder::new().create(context.new_module("bos")).unwrap();
+
+        let tokens = tokenize("function kare(x: i64) x * x;");
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+        let mut module = build_module(&mut context, "kare", &ast).unwrap();
+        for _ in 0..3 {
+            let frozen = engine.add_module(module);
+            let kare = engine.get_function::<extern "C" fn(i64) -> i64>("kare").unwrap();
+            assert_eq!(kare.call((6,)), 36);
+
+            module = unsafe { engine.remove_module(frozen) }.unwrap();
+            assert!(module.to_string().contains("define i64 @kare"));
+        }
+
+        // modules and the engine keep the LLVM context alive after the code generator is gone
+        drop(context);
+        let frozen = engine.add_module(module);
+        assert_ne!(engine.get_function_address("kare"), 0);
+        drop(unsafe { engine.remove_module(frozen) }.unwrap());
+        drop(engine);
+
+        // LLVM error messages are copied and freed exactly once
+        let module = Context::new().new_module("bozuk");
+        unsafe {
+            llvm_sys::core::LLVMSetTarget(module.to_ref(), c"bilinmeyen-triple".as_ptr());
+        }
+        assert_eq!(
+            MCJITBuilder::new().create(module).err(),
+            Some("No available targets are compatible with triple \"bilinmeyen-triple\"".to_string())
+        );
+        assert!(TargetMachine::new("bilinmeyen-triple").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_typed_functions() {
+        use super::codegen::builder::{build_module, Context};
+        use super::codegen::jitter::{link_in_interpreter, ExecutionEngine, MCJITBuilder};
+        use super::codegen::value::GenericValue;
+
+        let engine = jit_engine("function kare(x: i64) -> i64 x * x; function ters(b: bool) -> bool if b then false else true;");
+        assert_eq!(engine.get_function::<extern "C" fn(i64) -> i64>("kare").unwrap().call((-3,)), 9);
+        assert_eq!(
+            engine.get_function::<extern "C" fn(f64) -> f64>("kare").err(),
+            Some("function kare has type i64 (i64), not double (double)".to_string())
+        );
+        assert!(engine.get_function::<extern "C" fn(i64, i64) -> i64>("kare").is_err());
+        assert!(engine.get_function::<extern "C" fn(i64) -> i32>("kare").is_err());
+        assert!(engine.get_function::<extern "C" fn() -> f64>("yok").is_err());
+        assert!(engine.get_function::<extern "C" fn(bool) -> bool>("ters").unwrap().call((false,)));
+
+        // without zeroext the upper bits of an i1 are undefined, so it is not read as a bool
+        let tokens = tokenize("function ters(b: bool) -> bool if b then false else true;");
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+        let module = build_module(&mut Context::new(), "test", &ast).unwrap();
+        unsafe {
+            let ters = llvm_sys::core::LLVMGetNamedFunction(module.to_ref(), c"ters".as_ptr());
+            let zeroext = llvm_sys::core::LLVMGetEnumAttributeKindForName(c"zeroext".as_ptr(), "zeroext".len());
+            llvm_sys::core::LLVMRemoveEnumAttributeAtIndex(ters, llvm_sys::LLVMAttributeReturnIndex, zeroext);
+        }
+        let engine = MCJITBuilder::new().create(module).unwrap();
+        assert_eq!(
+            engine.get_function::<extern "C" fn(bool) -> bool>("ters").err(),
+            Some("function ters has type i1 (zeroext i1), not zeroext i1 (zeroext i1)".to_string())
+        );
+
+        let tokens = tokenize("function karisik(x: i32, y: f32, b: bool) -> f64 if b then 0.5 else 1.5;");
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+        link_in_interpreter();
+        let mut context = Context::new();
+        let engine = ExecutionEngine::new_interpreter(build_module(&mut context, "test", &ast).unwrap()).unwrap();
+        let result = engine.run_function("karisik", &[GenericValue::from_i32(-1), GenericValue::from_f32(2.5), GenericValue::from_bool(true)]);
+        assert_eq!(result.unwrap().to_f64(), 0.5);
+        assert_eq!(
+            engine.run_function("karisik", &[GenericValue::from_i32(-1)]).err(),
+            Some("function karisik takes 3 argument(s), not 1".to_string())
+        );
+        assert!(engine.run_function("yok", &[]).is_err());
+
+        assert_eq!(GenericValue::from_i32(-1).to_i64(), -1);
+        assert_eq!(GenericValue::from_i32(-1).to_u64(), u32::MAX as u64);
+        assert_eq!(GenericValue::from_u64(u64::MAX).int_width(), 64);
+        assert!(GenericValue::from_bool(true).to_bool());
+        assert_eq!(GenericValue::from_f32(0.25).to_f32(), 0.25);
+        let mut sayi = 7;
+        assert_eq!(unsafe { *GenericValue::from_ptr(&mut sayi).to_ptr::<i32>() }, 7);
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_host_functions() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use super::codegen::jitter::HostFunction;
+        use super::driver::repl::{Repl, ReplStatus};
+
+        static SAYAC: AtomicI64 = AtomicI64::new(0);
+        extern "C" fn sayac() -> i64 {
+            SAYAC.fetch_add(1, Ordering::SeqCst) + 1
+        }
+        extern "C" fn iki_kat(x: f64) -> f64 {
+            x * 2.0
+        }
+
+        let engine = jit_engine("extern sayac() -> i64; function iki_kez() -> i64 sayac() * 10 + sayac();");
+        engine.add_host_function("sayac", HostFunction::new(sayac as extern "C" fn() -> i64)).unwrap();
+        assert!(engine.add_host_function("sayac", HostFunction::new(sayac as extern "C" fn() -> i64)).is_err());
+        assert_eq!(engine.get_function::<extern "C" fn() -> i64>("iki_kez").unwrap().call(()), 12);
+
+        // the mapping outlives the modules using it
+        {
+            use super::codegen::builder::{build_module, Context};
+            use super::codegen::jitter::MCJITBuilder;
+
+            let mut context = Context::new();
+            let engine = MCJITBuilder::new().create(context.new_module("bos")).unwrap();
+            engine.add_host_function("iki_kat", HostFunction::new(iki_kat as extern "C" fn(f64) -> f64)).unwrap();
+
+            let tokens = tokenize("extern iki_kat(x: f64) -> f64; function dort_kat(x: f64) -> f64 iki_kat(iki_kat(x));");
+            let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+            assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+            let mut module = build_module(&mut context, "dort_kat", &ast).unwrap();
+            for x in 0..3 {
+                engine.verify_host_functions(&module).unwrap();
+                let frozen = engine.add_module(module);
+                let dort_kat = engine.get_function::<extern "C" fn(f64) -> f64>("dort_kat").unwrap();
+                assert_eq!(dort_kat.call((x as f64,)), x as f64 * 4.0);
+                module = unsafe { engine.remove_module(frozen) }.unwrap();
+            }
+        }
+
+        let mut repl = Repl::new().unwrap();
+        repl.add_host_function("iki_kat", HostFunction::new(iki_kat as extern "C" fn(f64) -> f64)).unwrap();
+        let mut feed = |line: &str| match repl.feed(line) {
+            Ok(ReplStatus::Complete(output)) => Ok(output),
+            Ok(_) => unreachable!(),
+            Err(message) => Err(message)
+        };
+
+        assert_eq!(feed("extern iki_kat(x); iki_kat(4)"), Ok(vec!["8".to_string()]));
+        feed(":reset").unwrap();
+        assert_eq!(
+            feed("extern iki_kat(x: i64) -> i64;"),
+            Err("extern iki_kat has type i64 (i64), but the host function has type double (double)".to_string())
+        );
+        assert_eq!(feed("extern iki_kat(x: f64) -> f64; iki_kat(0.25)"), Ok(vec!["0.5".to_string()]));
+    }
+
+    #[test]
+    #[cfg(feature = "llvm")]
+    fn test_orc_engine() {
+        use super::codegen::builder::{build_module, Context};
+        use super::codegen::jitter::{initialize_native_target, HostFunction};
+        use super::codegen::orc::OrcEngine;
+        use super::driver::repl::{Repl, ReplStatus};
+
+        extern "C" fn yok() -> f64 {
+            0.5
+        }
+
+        initialize_native_target();
+        let mut engine = OrcEngine::new().unwrap();
+        let mut context = Context::with_handle(engine.context().clone());
+        let mut checker = TypeChecker::new().allow_redefinition();
+        let mut add = |engine: &mut OrcEngine, input: &str| {
+            let tokens = tokenize(input);
+            let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+            assert_eq!(checker.check(&mut ast), vec![]);
+            engine.add_module(build_module(&mut context, "test", &ast).unwrap())
+        };
+
+        // nothing is compiled before it is called, so yok only has to exist by then
+        add(&mut engine, "extern yok() -> f64; function a() -> f64 yok() + 1;").unwrap();
+        add(&mut engine, "function f() -> i64 1; function g() -> i64 f() * 10;").unwrap();
+        assert_eq!(engine.get_function::<extern "C" fn() -> i64>("g").unwrap().call(()), 10);
+        engine.add_host_function("yok", HostFunction::new(yok as extern "C" fn() -> f64)).unwrap();
+        assert_eq!(engine.get_function::<extern "C" fn() -> f64>("a").unwrap().call(()), 1.5);
+
+        add(&mut engine, "function f() -> i64 2;").unwrap();
+        assert_eq!(engine.get_function::<extern "C" fn() -> i64>("g").unwrap().call(()), 20);
+        assert_eq!(
+            add(&mut engine, "function f() -> f64 2.5;"),
+            Err("function f of type i64 () cannot be redefined with type double ()".to_string())
+        );
+
+        unsafe {
+            engine.remove_function("a").unwrap();
+        }
+        assert!(engine.get_function::<extern "C" fn() -> f64>("a").is_err());
+        assert_eq!(engine.take_errors(), Vec::<String>::new());
+        assert_eq!(engine.shutdown(), Ok(()));
+
+        let mut repl = Repl::new_orc().unwrap();
+        let mut feed = |line: &str| match repl.feed(line) {
+            Ok(ReplStatus::Complete(output)) => Ok(output),
+            Ok(_) => unreachable!(),
+            Err(message) => Err(message)
+        };
+        feed("function kare(x) x * x; function kup(x) kare(x) * x;").unwrap();
+        assert_eq!(feed("kup(2)"), Ok(vec!["8".to_string()]));
+        feed("function kare(x) x + x;").unwrap();
+        assert_eq!(feed("kup(2)"), Ok(vec!["8".to_string()]));
+        assert_eq!(feed("kup(3)"), Ok(vec!["18".to_string()]));
+        assert!(feed("function kare(x: i32) -> i32 x;").is_err());
+        assert_eq!(feed("kare(5)"), Ok(vec!["10".to_string()]));
+    }
+
+    #[test]
+    fn test_interpreter() {
+        use super::interpreter::eval::Interpreter;
+
+        let programs = [
+            ("function kare(x: i32) -> i32 x * x; kare(50000); kare(-3) - 1", vec!["-1794967296", "8"]),
+            ("function kucuk(x: u32, y: u32) -> bool x < y; kucuk(1, 0 - 1); kucuk(0 - 1, 1)", vec!["true", "false"]),
+            ("function yarim(x: f32) -> f32 if x < 1 then x else x * 0.5; yarim(3); yarim(0.25)", vec!["1.5", "0.25"]),
+            ("function fib(n: i64) -> i64 if n < 2 then n else fib(n - 1) + fib(n - 2); fib(20)", vec!["6765"]),
+            ("function say(n) let t = 0 in for i = 0, 1 i < n in t + i; say(5); say(0)", vec!["0", "0"]),
+            ("function binary@ 50 (a, b) a - b * 2; function unary$ (v) if v then 0 else 1; 7 @ 2; $0; $(3 @ 1)", vec!["3", "1", "0"]),
+            ("function f(x) let a = x, b = a * 2 in let a = b + 1 in a * b + x; f(3)", vec!["45"]),
+            ("extern sin(x); extern pow(x, y); sin(0) + pow(2, 10)", vec!["1024"]),
+            ("function f(a: i32, b: i32) -> bool a <= b; f(2, 2); f(3, 2); 0 - 1 >= 0; 1 + 2 == 3; 1 < 2 == true", vec!["true", "false", "false", "true", "true"]),
+            ("function sq(x) x * x; function nan() sq(sq(sq(sq(sq(sq(1000000.0)))))) * 0; nan() == nan(); nan() != nan(); nan() > 1; 2.5 != 2.5", vec!["false", "true", "true", "false"]),
+            ("function f(a: u32) a > 1; f(0 - 1); true != false", vec!["true", "true"]),
+            ("function f(a: i32, b: i32) -> i32 a / b; f(7, 2); f(0 - 7, 2); (0 - 7) % 3; 7.5 / 2; 7.5 % 2", vec!["3", "-3", "-1", "3.75", "1.5"]),
+            ("function g(a: u32, b: u32) -> u32 a / b + a % b; function h(a: u64) -> u64 a >> 60; g(0 - 1, 2); h(0 - 1)", vec!["2147483648", "15"]),
+            ("function f(x: i32) x > 0 && x < 10; f(5); f(10); 0 || 2.5; !0; !(1 < 2) || false; true || false && false", vec!["true", "false", "true", "true", "false", "true"]),
+            ("1 < 2 || 1 / 0 == 0; 1 > 2 && 1 / 0 == 0; !!3", vec!["true", "false", "true"]),
+            ("function f(x: i64) -> i64 { x + 1; let y = x * 2 in { y; y + 3 } }; f(4); if f(1) > 4 then { 1; 2 } else { 3 }; for i = 0, 1 i < 3 in { i; i * 2 }", vec!["11", "2", "0"]),
+            ("function sum(n: i64) -> i64 let mut t = 0 in { for i = 0, 1 i < n in t = t + i; t }; sum(5); sum(1)", vec!["15", "1"]),
+            ("let mut a = 1, mut b = 2 in { a = b = a + b; a * b }; function f(x: f32) -> f32 let mut y = x in { y = y * 2; let y = y + 1 in y }; f(1.5)", vec!["9", "4"]),
+            ("function collatz(n: i64) -> i64 let mut n = n, mut adim = 0 in { while n != 1 { n = if n % 2 == 0 then n / 2 else 3 * n + 1; adim = adim + 1 }; adim }; collatz(27)", vec!["111"]),
+            ("function kok(n: u32) -> u32 let mut i = 0 in loop { i = i + 1; if i * i > n then break i - 1 else 0 }; kok(50); loop { break }; while false { 1 }", vec!["7", "0", "0"]),
+            ("let mut t = 0 in { 'dis: for i = 0, 1 i < 4 in for j = 0, 1 j < 4 in { if j > i then continue 'dis else 0; if i == 3 then break 'dis else 0; t = t + 1 }; t }", vec!["6"]),
+            ("let mut i = 0, mut t = 0 in 'dis: loop { i = i + 1; loop { if i > 3 then break 'dis t else break }; if i % 2 == 0 then continue else 0; t = t + i }", vec!["4"]),
+            ("function ilk_bolen(n: i64) -> i64 { for i = 2, 1 i < n in if n % i == 0 then return i; n }; ilk_bolen(91); ilk_bolen(13)", vec!["7", "13"]),
+            ("function bul(x: i32) -> i32 let mut i = 0 in loop { i = i + 1; while true { if i * i > x then return i; break } }; bul(50)", vec!["8"]),
+            ("function mutlak(x: i64) -> i64 if x > 0 then return x else 0 - x; mutlak(3); mutlak(0 - 4); return 3; 4", vec!["3", "4", "3", "4"]),
+            ("function isaret(x: i32) -> i32 if x < 0 then 0 - 1 else if x == 0 then 0 else 1; isaret(0 - 5); isaret(0); isaret(7)", vec!["-1", "0", "1"]),
+            ("let mut t = 0 in { for i = 0, 1 i < 5 in if i % 2 == 0 then t = t + i; t }; if 1 > 2 then 5; let mut x = 1 in { if x > 0 then x = 10 else if x < 0 then x = 20; x }", vec!["6", "()", "10"]),
+            ("6 & 3; 6 | 3; 6 ^ 3; ~5; 1 << 65; 0 - 16 >> 2; 1 | 2 ^ 3 & 5; 1 + 6 / 4 * 2", vec!["2", "7", "5", "-6", "2", "-4", "3", "3"]),
+        ];
+
+        for (program, expected) in programs.iter() {
+            let tokens = tokenize(program);
+            let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+            assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+            let interpreted: Vec<String> = Interpreter::new().run(&ast).unwrap().iter()
+                .map(|value| value.to_string())
+                .collect();
+            assert_eq!(&interpreted, expected, "{}", program);
+
+            // the compiled program has to agree with the interpreter
+            #[cfg(feature = "llvm")]
+            {
+                use super::driver::repl::{Repl, ReplStatus};
+
+                let mut repl = Repl::new().unwrap();
+                match repl.feed(program).unwrap() {
+                    ReplStatus::Complete(output) => assert_eq!(output, interpreted, "{}", program),
+                    _ => unreachable!()
+                }
+            }
+        }
+
+        let tokens = tokenize("extern cos(x); extern gizli(x); cos(0); gizli(1)");
+        let (ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.run(&ast), Err("1:23: extern gizli is not available in the interpreter".to_string()));
+        interpreter.add_host_function("gizli", |args| Ok(args[0]));
+        assert_eq!(interpreter.run(&ast).unwrap().iter().map(|value| value.to_string()).collect::<Vec<_>>(), vec!["1", "1"]);
+    }
+
+    #[test]
+    fn test_session() {
+        use super::Session;
+
+        let program = || {
+            let mut session = Session::new();
+            let kare = session.add_file("kare.tbc", "function binary@ 70 (a, b) a * b; function kare(x) x @ x;");
+            let main = session.add_file("main.tbc", "kare(12); 2 @ 3");
+            (session, kare, main)
+        };
+
+        let (mut session, kare, main) = program();
+        let mut ast = session.parse(kare).unwrap();
+        assert_eq!(session.check(kare, &mut ast), Ok(()));
+        let mut ast = session.parse(main).unwrap();
+        assert_eq!(session.check(main, &mut ast), Ok(()));
+        assert!(session.diagnostics().is_empty());
+
+        // operators defined in one file are known in the next
+        #[cfg(feature = "llvm")]
+        {
+            assert_eq!(program().0.jit_eval(), Ok(vec!["144".to_string(), "6".to_string()]));
+
+            // files checked before are not checked again
+            let (mut session, kare, _) = program();
+            let mut ast = session.parse(kare).unwrap();
+            assert_eq!(session.check(kare, &mut ast), Ok(()));
+            assert_eq!(session.jit_eval(), Ok(vec!["144".to_string(), "6".to_string()]));
+            assert!(session.diagnostics().is_empty());
+
+            let mut broken = Session::new();
+            let file = broken.add_file("bozuk.tbc", "function f(x: i32) -> bool x + 1;");
+            let mut ast = broken.parse(file).unwrap();
+            assert!(broken.check(file, &mut ast).is_err());
+            assert_eq!(broken.jit_eval(), Err("aborting due to 1 previous error(s)".to_string()));
+            assert_eq!(broken.diagnostics().len(), 1);
+        }
+
+        let mut broken = Session::new();
+        let file = broken.add_file("bozuk.tbc", "function f(x: i32) -> bool x + 1;\nfunction g(x y;");
+        let mut ast = broken.parse(file).unwrap();
+        assert_eq!(broken.check(file, &mut ast), Err("aborting due to 2 previous error(s)".to_string()));
+        assert_eq!(broken.report().lines().next(), Some("bozuk.tbc:2:15: error: expected ')' in prototype"));
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        let tokens = tokenize("a<=b==c!=-d>=e->");
+        let kinds: Vec<_> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(kinds, vec![
+            Ident("a".to_string()), Operator("<=".to_string()), Ident("b".to_string()),
+            Operator("==".to_string()), Ident("c".to_string()), Operator("!=".to_string()),
+            Operator("-".to_string()), Ident("d".to_string()), Operator(">=".to_string()),
+            Ident("e".to_string()), Arrow,
+        ]);
+
+        let input = r#"
+            function f(a: bool, b: bool) a == b;
+            function g(a: bool, b: bool) a < b;
+            function h(x: i32) x != 1.5;
+        "#;
+        assert_eq!(check_messages(input), vec![
+            "3:42: error: operator < is not defined for type bool",
+            "4:37: error: expected i32, found {float}",
+        ]);
+    }
+
+    #[test]
+    fn test_arithmetic_operators() {
+        let tokens = tokenize("function binary% 50 (a, b) a;");
+        assert_eq!(
+            parse(&tokens, &[], &mut default_parser_settings()).unwrap_err(),
+            "1:16: operator % is built in and cannot be redefined"
+        );
+        let tokens = tokenize("function binary= 5 (a, b) a;");
+        assert_eq!(
+            parse(&tokens, &[], &mut default_parser_settings()).unwrap_err(),
+            "1:16: operator = is built in and cannot be redefined"
+        );
+
+        let input = r#"
+            function f(x: f64) x << 1;
+            function g(x: f32) ~x;
+            function h(x) x & 1.5;
+            function k(x, y) (x ^ y) / 2;
+        "#;
+        assert_eq!(check_messages(input), vec![
+            "2:32: error: operator << is not defined for type f64",
+            "3:32: error: operator ~ is not defined for type f32",
+            "4:31: error: expected {integer}, found {float}",
+        ]);
+
+        use super::ast::types::I64;
+        let tokens = tokenize("function k(x, y) (x ^ y) / 2;");
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        let mut checker = TypeChecker::new();
+        assert_eq!(checker.check(&mut ast), vec![]);
+        let signature = checker.signature("k").map(|signature| (signature.args, signature.ret));
+        assert_eq!(signature, Some((vec![I64, I64], I64)));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use super::interpreter::eval::Interpreter;
+        use super::interpreter::value::BoolValue;
+
+        let input = r#"
+            extern dokun(x: bool) -> bool;
+            function f(a: bool, b: bool) -> bool dokun(a) && dokun(b) || dokun(true);
+            f(false, true); f(true, true);
+        "#;
+        let tokens = tokenize(input);
+        let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+        let calls = Rc::new(Cell::new(0));
+        let mut interpreter = Interpreter::new();
+        let counter = calls.clone();
+        interpreter.add_host_function("dokun", move |args| {
+            counter.set(counter.get() + 1);
+            Ok(args[0])
+        });
+        assert_eq!(interpreter.run(&ast), Ok(vec![BoolValue(true), BoolValue(true)]));
+        assert_eq!(calls.get(), 2 + 2);
+
+        assert_eq!(check_messages("function f(x: f64) (x && 1) + 1;"), vec!["1:20: error: operator + is not defined for type bool"]);
+
+        for op in ["!", "-", "~"] {
+            let tokens = tokenize(&format!("function unary{} (v) v;", op));
+            assert_eq!(
+                parse(&tokens, &[], &mut default_parser_settings()).unwrap_err(),
+                format!("1:15: operator {} is built in and cannot be redefined", op)
+            );
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        use super::ast::ast::{parse_recovering, BlockExpr, BinaryExpr, VariableExpr};
+
+        let tokens = tokenize("function f(x) { x; x + 1 }");
+        let (ast, rest) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert!(rest.is_empty());
+        match ast[0] {
+            FunctionsNode(ref function) => match function.body.kind {
+                BlockExpr(ref exprs) => {
+                    assert_eq!(exprs.len(), 2);
+                    assert_eq!(exprs[0].kind, VariableExpr("x".to_string()));
+                    assert!(matches!(exprs[1].kind, BinaryExpr(ref op, _, _) if op == "+"));
+                    assert_eq!(function.body.span.to_string(), "1:15");
+                },
+                ref kind => panic!("expected a block, found {:?}", kind)
+            },
+            _ => unreachable!()
+        }
+
+        // an unfinished block waits for more input
+        let tokens = tokenize("{ 1; 2");
+        let (ast, rest) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert!(ast.is_empty());
+        assert_eq!(rest.len(), 4);
+
+        let tokens = tokenize("{ 1 2 }");
+        assert_eq!(parse(&tokens, &[], &mut default_parser_settings()).err(), Some("1:5: expected ';' or '}'".to_string()));
+
+        let tokens = tokenize("function f(x) { x; x + 1; }");
+        let (ast, rest) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        assert!(rest.is_empty());
+        assert!(matches!(ast[0], FunctionsNode(ref function) if matches!(function.body.kind, BlockExpr(ref exprs) if exprs.len() == 2)));
+
+        // recovery skips the rest of the block instead of parsing it as top-level code
+        let tokens = tokenize("function f(x) { x $ 1; x + 1; { x } };\nfunction g(x) x;");
+        let (ast, rest, diagnostics) = parse_recovering(&tokens, &[], &mut default_parser_settings());
+        let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+        assert_eq!(messages, vec!["1:19: error: unknown operator found"]);
+        assert_eq!(ast.len(), 1);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_assignment() {
+        let tokens = tokenize("let mut x = 1 in x + 1 = 2");
+        assert_eq!(parse(&tokens, &[], &mut default_parser_settings()).err(),
+            Some("1:18: invalid left-hand side of assignment".to_string()));
+
+        let input = r#"
+            function f(x) if x = 5 then 1 else 0;
+            function g() let a = 1 in a = 2;
+            function h() let mut b = 1 in b = true;
+        "#;
+        assert_eq!(check_messages(input), vec![
+            "2:30: error: cannot assign to immutable variable x",
+            "3:39: error: cannot assign to immutable variable a",
+            "4:47: error: expected {number}, found bool",
+        ]);
+
+        #[cfg(feature = "llvm")]
+        {
+            use super::codegen::builder::{build_module, Context};
+            use super::codegen::optimize::PassPipeline;
+
+            let tokens = tokenize("function f(n: i32) -> i32 let mut t = 0 in { for i = 0, 1 i < n in t = t + i; t };");
+            let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+            assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+            let mut context = Context::new();
+            let module = build_module(&mut context, "test", &ast).unwrap();
+            assert!(module.to_string().contains("%t = alloca i32"));
+            PassPipeline::new("function(mem2reg)").verify_each().run(&module, None).unwrap();
+            assert!(!module.to_string().contains("alloca"));
+        }
+    }
+
+    #[test]
+    fn test_loops() {
+        let tokens = tokenize("'dis: while x < 10 { break 'dis }");
+        let kinds: Vec<_> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(kinds[..4], [Label("dis".to_string()), Colon, While, Ident("x".to_string())]);
+        assert_eq!(kinds[kinds.len() - 3..], [Break, Label("dis".to_string()), ClosingBrackets]);
+
+        let errors = [
+            ("break", "1:1: break outside of a loop"),
+            ("function f() continue;", "1:14: continue outside of a loop"),
+            ("while true { continue 'dis }", "1:14: use of undeclared label 'dis"),
+            ("for i = 0, 1 i < 3 in break i", "1:29: only loop can be left with a value, not while or for"),
+            ("while true 1", "1:12: expected '{' before the loop body"),
+            ("'dis: 1", "1:7: expected a loop after the label"),
+        ];
+        for (program, expected) in errors.iter() {
+            let tokens = tokenize(program);
+            assert_eq!(parse(&tokens, &[], &mut default_parser_settings()).err().as_deref(), Some(*expected), "{}", program);
+        }
+
+        // the loop a break refers to is gone once the loop is parsed
+        let mut settings = default_parser_settings();
+        assert!(parse(&tokenize("loop { break }"), &[], &mut settings).is_ok());
+        assert!(parse(&tokenize("break"), &[], &mut settings).is_err());
+
+        assert_eq!(check_messages("function f(x: i32) loop { if x > 0 then break x else break true };"), vec!["1:60: error: expected i32, found bool"]);
+    }
+
+    #[test]
+    fn test_optional_else() {
+        use super::ast::ast::ConditionalExpr;
+
+        // a dangling else goes with the innermost if
+        let tokens = tokenize("if a then if b then 1 else 2");
+        let (ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+        match ast[0] {
+            FunctionsNode(ref function) => match function.body.kind {
+                ConditionalExpr{ref then_expr, ref else_expr, ..} => {
+                    assert!(else_expr.is_none());
+                    assert!(matches!(then_expr.kind, ConditionalExpr{else_expr: Some(_), ..}));
+                },
+                ref kind => panic!("expected a conditional, found {:?}", kind)
+            },
+            _ => unreachable!()
+        }
+
+        let input = r#"
+            function f(x: i64) -> i64 if x > 0 then x;
+            function g(x) (if x then 1) + 2;
+            function h(x) if (if x then 1) then 2 else 3;
+            function k(x) !(if x then 1);
+            function m(x: bool) -> i64 if x then 1 else if !x then 2;
+            function n(x: bool) -> i64 if x then (if !x then 1) else 2;
+            function u(x: bool) if x then 1;
+            function v(x: bool) if x then 1 else u(x);
+            function w(x: bool) -> i64 let a = 1 in { u(x); if x then a };
+        "#;
+        let messages = check_messages(input);
+        let missing_else = "error: `if` without `else` has type (), add an else branch";
+        assert_eq!(messages, vec![
+            format!("2:39: {}", missing_else),
+            format!("3:27: {}", missing_else),
+            format!("4:30: {}", missing_else),
+            format!("5:28: {}", missing_else),
+            format!("6:57: {}", missing_else),
+            format!("7:50: {}", missing_else),
+            "9:50: error: expected {number}, found ()".to_string(),
+            format!("10:61: {}", missing_else),
+        ]);
+    }
+
+    #[test]
+    fn test_return() {
+        let tokens = tokenize("function f() return");
+        assert!(matches!(parse(&tokens, &[], &mut default_parser_settings()), Ok((ref ast, ref rest)) if ast.is_empty() && rest.len() == 5));
+
+        let input = r#"
+            function f(x: i64) -> i64 if x > 0 then return true else x;
+            function g(x: bool) -> bool { return 1; x };
+        "#;
+        assert_eq!(check_messages(input), vec![
+            "2:60: error: expected i64, found bool",
+            "3:50: error: expected bool, found {number}",
+        ]);
+
+        #[cfg(feature = "llvm")]
+        {
+            use super::codegen::builder::{build_module, Context};
+
+            let tokens = tokenize("function f(x: i32) -> i32 { while x > 0 { return x }; 0 };");
+            let (mut ast, _) = parse(&tokens, &[], &mut default_parser_settings()).unwrap();
+            assert_eq!(TypeChecker::new().check(&mut ast), vec![]);
+
+            let mut context = Context::new();
+            let module = build_module(&mut context, "test", &ast).unwrap();
+            let ir = module.to_string();
+            assert!(ir.contains("%rettmp = phi i32"));
+            assert_eq!(ir.matches("ret i32").count(), 1);
+        }
+    }
+}
\ No newline at end of file
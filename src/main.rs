@@ -0,0 +1,250 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+use docopt::{ArgvMap, Docopt};
+
+use tobichi::lexer::tokens::try_tokenize;
+use tobichi::ast::ast::{ASTNode, FunctionsNode};
+use tobichi::codegen::builder::{build_entry_point, ANONYMOUS_FUNCTION_NAME};
+use tobichi::codegen::jitter::{initialize_native_target, link_in_mcjit, MCJITBuilder};
+use tobichi::codegen::optimize::{enable_print_after_all, OptLevel, PassPipeline, KNOWN_PASSES};
+use tobichi::codegen::target::{write_bitcode_to_file, LLVMCodeGenFileType, TargetMachine};
+use tobichi::driver::link::{executable_name, link_executable};
+use tobichi::driver::repl::{call_anonymous, Repl, ReplStatus};
+use tobichi::driver::session::{FileId, Session};
+use tobichi::interpreter::eval::Interpreter;
+
+const USAGE: &str = "
+Tobichi compiler.
+
+Usage:
+    tobichi run <file> [--interpret] [-O <level>] [--passes=<pipeline>] [--print-after-all]
+    tobichi build <file> [--emit=<kind>] [--target=<triple>] [-o <output>] [-O <level>] [--passes=<pipeline>] [--print-after-all]
+    tobichi check <file>
+    tobichi repl [--orc]
+    tobichi passes
+    tobichi (-h | --help)
+    tobichi --version
+
+Options:
+    -h --help               Show this screen.
+    --version               Show version.
+    --emit=<kind>           What to produce: tokens, ast, llvm-ir, bc, asm, obj or exe [default: exe].
+    --target=<triple>       Target to compile for instead of the host.
+    -o <output>             Output file, tokens, ast and llvm-ir go to stdout without it.
+    -O <level>              Optimization level: 0, 1, 2, 3, s or z [default: 0].
+    --passes=<pipeline>     Run this pass pipeline instead of the one of the -O level.
+    --print-after-all       Print the IR after every optimization pass to stderr.
+    --interpret             Evaluate the program with the interpreter instead of compiling it.
+    --orc                   Use the ORC JIT, which compiles lazily and allows redefining functions.
+";
+
+fn main() {
+    let args = Docopt::new(USAGE)
+        .and_then(|d| d.version(Some(env!("CARGO_PKG_VERSION").to_string())).parse())
+        .unwrap_or_else(|e| e.exit());
+
+    if args.get_bool("repl") {
+        if let Err(message) = repl(args.get_bool("--orc")) {
+            eprintln!("error: {}", message);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.get_bool("passes") {
+        for (name, description) in KNOWN_PASSES {
+            println!("{:<16}{}", name, description);
+        }
+        println!("\npresets: default<O0> ... default<O3>, default<Os> and default<Oz>, see -O");
+        return;
+    }
+
+    let file = args.get_str("<file>");
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", file, err);
+            process::exit(1);
+        }
+    };
+
+    let mut session = Session::new();
+    let id = session.add_file(file, &source);
+
+    let result = if args.get_bool("run") {
+        run(&mut session, id, &args)
+    } else if args.get_bool("build") {
+        build(&mut session, id, &args)
+    } else {
+        frontend(&mut session, id).map(|_| ())
+    };
+
+    if let Err(message) = result {
+        eprintln!("error: {}", message);
+        process::exit(1);
+    }
+}
+
+fn repl(orc: bool) -> Result<(), String> {
+    let mut repl = if orc { Repl::new_orc()? } else { Repl::new()? };
+    let stdin = io::stdin();
+
+    loop {
+        print!("{}", if repl.is_pending() { "... " } else { "> " });
+        io::stdout().flush().map_err(|err| err.to_string())?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        match repl.feed(line.trim_end_matches('\n')) {
+            Ok(ReplStatus::Complete(output)) => for line in output {
+                println!("{}", line);
+            },
+            Ok(ReplStatus::NeedMore) => (),
+            Ok(ReplStatus::Quit) => return Ok(()),
+            Err(message) => eprintln!("error: {}", message)
+        }
+    }
+}
+
+/// Lexes, parses and type checks `file`, reporting every diagnostic before giving up.
+fn frontend(session: &mut Session, file: FileId) -> Result<Vec<ASTNode>, String> {
+    let result = session.parse(file).and_then(|mut ast| session.check(file, &mut ast).map(|_| ast));
+    eprint!("{}", session.report());
+    result
+}
+
+/// Optimization level and IR pipeline asked for on the command line.
+fn optimization(args: &ArgvMap) -> Result<(OptLevel, PassPipeline), String> {
+    let level = OptLevel::from_name(args.get_str("-O"))
+        .ok_or_else(|| format!("unknown optimization level -O{}", args.get_str("-O")))?;
+    let pipeline = match args.get_str("--passes") {
+        "" => PassPipeline::preset(level),
+        passes => PassPipeline::new(passes).verify_each()
+    };
+
+    if args.get_bool("--print-after-all") {
+        enable_print_after_all();
+    }
+
+    Ok((level, pipeline))
+}
+
+fn run(session: &mut Session, id: FileId, args: &ArgvMap) -> Result<(), String> {
+    let (level, pipeline) = optimization(args)?;
+    let ast = frontend(session, id)?;
+    let file = session.file(id).name.clone();
+
+    if args.get_bool("--interpret") {
+        for value in Interpreter::new().run(&ast).map_err(|message| format!("{}:{}", file, message))? {
+            println!("{}", value);
+        }
+        return Ok(());
+    }
+
+    link_in_mcjit();
+    initialize_native_target();
+
+    let module = session.compile_to_module(&file, &ast).map_err(|message| format!("{}:{}", file, message))?;
+    let anonymous: Vec<String> = module.defined_functions().into_iter()
+        .filter(|name| name.starts_with(ANONYMOUS_FUNCTION_NAME))
+        .collect();
+
+    let return_types = ast.iter().filter_map(|node| match *node {
+        FunctionsNode(ref function) if function.prototype.name.is_empty() => function.prototype.ret_type,
+        _ => None
+    });
+
+    let machine = TargetMachine::host()?;
+    machine.prepare_module(&module);
+    pipeline.run(&module, Some(&machine))?;
+
+    let engine = MCJITBuilder::new().set_opt_level(level.codegen_level()).create(module)?;
+    for (name, ret) in anonymous.iter().zip(return_types) {
+        println!("{}", call_anonymous(&engine, name, ret)?);
+    }
+
+    Ok(())
+}
+
+fn build(session: &mut Session, id: FileId, args: &ArgvMap) -> Result<(), String> {
+    let file = session.file(id).name.clone();
+    let emit = args.get_str("--emit");
+    let output = match args.get_str("-o") {
+        "" => None,
+        output => Some(output.to_string())
+    };
+    let stem = Path::new(&file).file_stem().and_then(|stem| stem.to_str()).unwrap_or("out");
+
+    if emit == "tokens" {
+        let tokens = match try_tokenize(&session.file(id).source) {
+            Ok(tokens) => tokens,
+            // parsing reports the lexical errors
+            Err(_) => return frontend(session, id).map(|_| ())
+        };
+        return write_output(output, format!("{:#?}\n", tokens));
+    }
+
+    let (_, pipeline) = optimization(args)?;
+    let ast = frontend(session, id)?;
+    if emit == "ast" {
+        return write_output(output, format!("{:#?}\n", ast));
+    }
+
+    let target = match args.get_str("--target") {
+        "" => None,
+        triple => Some(triple)
+    };
+    let machine = match target {
+        Some(triple) => TargetMachine::new(triple)?,
+        None => TargetMachine::host()?
+    };
+
+    let module = session.compile_to_module(&file, &ast).map_err(|message| format!("{}:{}", file, message))?;
+    machine.prepare_module(&module);
+
+    if emit == "exe" {
+        build_entry_point(session.context(), &module).map_err(|message| format!("{}:{}", file, message))?;
+    }
+    pipeline.run(&module, Some(&machine))?;
+
+    match emit {
+        "llvm-ir" => write_output(output, module.to_string()),
+        "bc" => write_bitcode_to_file(&module, &output.unwrap_or_else(|| format!("{}.bc", stem))),
+        "asm" => {
+            let path = output.unwrap_or_else(|| format!("{}.s", stem));
+            machine.emit_to_file(&module, &path, LLVMCodeGenFileType::LLVMAssemblyFile)
+        },
+        "obj" => {
+            let path = output.unwrap_or_else(|| format!("{}.o", stem));
+            machine.emit_to_file(&module, &path, LLVMCodeGenFileType::LLVMObjectFile)
+        },
+        "exe" => {
+            let object = std::env::temp_dir()
+                .join(format!("{}-{}.o", stem, process::id()))
+                .to_string_lossy()
+                .into_owned();
+            machine.emit_to_file(&module, &object, LLVMCodeGenFileType::LLVMObjectFile)?;
+
+            let result = link_executable(&[&object], &output.unwrap_or_else(|| executable_name(stem)), target);
+            let _ = fs::remove_file(&object);
+            result
+        },
+        kind => Err(format!("unknown --emit kind {}", kind))
+    }
+}
+
+fn write_output(output: Option<String>, content: String) -> Result<(), String> {
+    match output {
+        Some(path) => fs::write(&path, content).map_err(|err| format!("could not write {}: {}", path, err)),
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
+    }
+}
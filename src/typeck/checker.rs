@@ -0,0 +1,754 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::ast::{
+    ASTNode,
+    Expression,
+    Functions,
+    Prototype,
+    ExternNode,
+    FunctionsNode,
+    LiteralExpr,
+    VariableExpr,
+    UnaryExpr,
+    BinaryExpr,
+    ConditionalExpr,
+    LoopExpr,
+    WhileExpr,
+    BreakExpr,
+    ContinueExpr,
+    ReturnExpr,
+    VarExpr,
+    AssignExpr,
+    BlockExpr,
+    CallExpr,
+    IntegerLiteral,
+    FloatLiteral,
+    BoolLiteral,
+    Normal,
+    BUILTIN_UNARY_OPERATORS,
+};
+use crate::ast::diagnostic::Diagnostic;
+use crate::ast::types::{Type, Bool, I64, F64, Unit};
+use crate::lexer::span::Span;
+use crate::typeck::infer::{Bound, Origin, Substitution, Ty, Variable};
+
+/// Operators the code generator implements itself, everything else has to be a
+/// user-defined `binary`/`unary` function.
+pub const BUILTIN_BINARY_OPERATORS: &[&str] = &[
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "<", "<=", ">", ">=", "==", "!=", "&&", "||",
+];
+pub const COMPARISON_OPERATORS: &[&str] = &["<", "<=", ">", ">=", "==", "!="];
+/// Operators taking conditions like `if` does and evaluating their right side only when
+/// it decides the result.
+pub const LOGICAL_OPERATORS: &[&str] = &["&&", "||"];
+
+/// What the operands of a built-in operator can be. Bools can only be compared for
+/// equality, bitwise operators and shifts only work on integers.
+fn operand_bound(operator: &str) -> Bound {
+    match operator {
+        "==" | "!=" | "!" => Bound::Value,
+        "&" | "|" | "^" | "<<" | ">>" | "~" => Bound::Integer,
+        _ => Bound::Numeric
+    }
+}
+
+/// Parameter types and return type of a function.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Signature {
+    pub args: Vec<Type>,
+    pub ret: Type
+}
+
+/// Type of a function that may be generic, `Ty::Var(i)` in `args` and `ret` stands
+/// for `vars[i]`.
+#[derive(Clone, Debug)]
+struct Scheme {
+    vars: Vec<Variable>,
+    args: Vec<Ty>,
+    ret: Ty
+}
+
+impl Scheme {
+    fn is_generic(&self) -> bool {
+        !self.vars.is_empty()
+    }
+
+    /// Types of the variables when a call does not decide them, this instance is
+    /// compiled under the plain name of the function.
+    fn defaults(&self) -> Vec<Type> {
+        self.vars.iter().map(Variable::default_type).collect()
+    }
+
+    fn apply(&self, types: &[Type]) -> Signature {
+        let apply = |ty: Ty| match ty {
+            Ty::Known(ty) => ty,
+            Ty::Var(index) => types[index]
+        };
+
+        Signature {
+            args: self.args.iter().map(|ty| apply(*ty)).collect(),
+            ret: apply(self.ret)
+        }
+    }
+}
+
+fn mangle(name: &str, types: &[Type]) -> String {
+    let types: Vec<String> = types.iter().map(Type::to_string).collect();
+    format!("{}.{}", name, types.join("."))
+}
+
+/// The `if` without `else` that gives `expr` its type `()`, looking into blocks, `let`
+/// bodies and `else if` chains.
+fn missing_else(expr: &Expression) -> Option<&Expression> {
+    match expr.kind {
+        ConditionalExpr{else_expr: None, ..} => Some(expr),
+        ConditionalExpr{else_expr: Some(ref else_expr), ..} => missing_else(else_expr),
+        BlockExpr(ref exprs) => exprs.last().and_then(missing_else),
+        VarExpr{ref body_expr, ..} => missing_else(body_expr),
+        _ => None
+    }
+}
+
+/// Subexpressions of `expr` in the order the checker visits them.
+fn children_mut(expr: &mut Expression) -> Vec<&mut Expression> {
+    match expr.kind {
+        LiteralExpr(_) | VariableExpr(_) => vec![],
+        UnaryExpr(_, ref mut operand) => vec![operand],
+        BinaryExpr(_, ref mut lhs, ref mut rhs) => vec![lhs, rhs],
+        ConditionalExpr{ref mut cond_expr, ref mut then_expr, ref mut else_expr} => {
+            let mut children: Vec<&mut Expression> = vec![cond_expr, then_expr];
+            children.extend(else_expr.iter_mut().map(|else_expr| &mut **else_expr));
+            children
+        },
+        LoopExpr{ref mut start_expr, ref mut end_expr, ref mut step_expr, ref mut body_expr, ..} => {
+            vec![start_expr, end_expr, step_expr, body_expr]
+        },
+        WhileExpr{ref mut cond_expr, ref mut body_expr, ..} => {
+            let mut children: Vec<&mut Expression> = cond_expr.iter_mut().map(|cond| &mut **cond).collect();
+            children.push(body_expr);
+            children
+        },
+        BreakExpr{ref mut value, ..} => value.iter_mut().map(|value| &mut **value).collect(),
+        ContinueExpr(_) => vec![],
+        ReturnExpr(ref mut value) => vec![value],
+        VarExpr{ref mut vars, ref mut body_expr} => {
+            let mut children: Vec<&mut Expression> = vars.iter_mut().map(|var| &mut var.2).collect();
+            children.push(body_expr);
+            children
+        },
+        AssignExpr(_, ref mut value) => vec![value],
+        BlockExpr(ref mut exprs) => exprs.iter_mut().collect(),
+        CallExpr(_, ref mut args) => args.iter_mut().collect()
+    }
+}
+
+/// Writes the solved types back into the tree and renames calls of generic functions
+/// to the instance they use. `types` is in post-order like the checker produced it.
+fn write_types(expr: &mut Expression, types: &[Type], renames: &HashMap<usize, String>, next: &mut usize) {
+    for child in children_mut(expr) {
+        write_types(child, types, renames, next);
+    }
+
+    match expr.kind {
+        VarExpr{ref mut vars, ..} => for var in vars.iter_mut() {
+            var.1 = var.2.ty;
+        },
+        CallExpr(ref mut name, _) => if let Some(instance) = renames.get(next) {
+            *name = instance.clone();
+        },
+        _ => ()
+    }
+
+    expr.ty = Some(types[*next]);
+    *next += 1;
+}
+
+/// Checks that every integer literal, or negated integer literal, fits the type it was
+/// given. Types have to be written into the tree already.
+fn check_literals(expr: &mut Expression) -> Result<(), Diagnostic> {
+    let value = match expr.kind {
+        LiteralExpr(IntegerLiteral(value)) => Some(value as i128),
+        UnaryExpr(ref operator, ref operand) if operator == "-" => match operand.kind {
+            LiteralExpr(IntegerLiteral(value)) => Some(-(value as i128)),
+            _ => None
+        },
+        _ => None
+    };
+
+    match (value, expr.ty.and_then(|ty| ty.integer_range())) {
+        (Some(value), Some((min, max))) if value < min || value > max => {
+            let message = format!("literal {} is out of range for {}", value, expr.ty.unwrap());
+            Err(Diagnostic::error(&message, expr.span))
+        },
+        (Some(_), _) => Ok(()),
+        _ => children_mut(expr).into_iter().try_for_each(check_literals)
+    }
+}
+
+/// State of checking one function body.
+struct Inference {
+    substitution: Substitution,
+    /// Type of every variable in scope and whether it can be assigned to.
+    variables: HashMap<String, (Ty, bool)>,
+    /// Label of every loop around the expression being checked, and the type of the
+    /// value `break` leaves it with if it can have one.
+    loops: Vec<(Option<String>, Option<Ty>)>,
+    /// Type of every expression, in post-order.
+    types: Vec<Ty>,
+    /// Calls of generic functions: position of the call in `types`, the callee and
+    /// the variables of its scheme.
+    instances: Vec<(usize, String, Vec<Ty>)>,
+    renames: HashMap<usize, String>,
+    /// Name used in the source, name of the instance being checked and its signature.
+    current: (String, String, Vec<Ty>, Ty),
+}
+
+impl Inference {
+    fn unify(&mut self, expected: Ty, found: Ty, expr: &Expression) -> Result<(), Diagnostic> {
+        let result = self.substitution.unify(expected, found);
+        result.map_err(|message| self.misuse(found, expr, &message, expr.span))
+    }
+
+    /// The error for `expr` of type `ty` used where it does not fit. When the `()` comes
+    /// from an `if` without `else`, that is pointed out instead of the types.
+    fn misuse(&self, ty: Ty, expr: &Expression, message: &str, span: Span) -> Diagnostic {
+        match missing_else(expr) {
+            Some(conditional) if self.substitution.resolve(ty) == Ty::Known(Unit) => {
+                Diagnostic::error("`if` without `else` has type (), add an else branch", conditional.span)
+            },
+            _ => Diagnostic::error(message, span)
+        }
+    }
+
+    /// The loop `break` or `continue` with `label` refers to.
+    fn loop_target(&self, keyword: &str, label: &Option<String>, span: Span) -> Result<usize, Diagnostic> {
+        let target = match *label {
+            Some(ref name) => self.loops.iter().rposition(|target| target.0.as_ref() == Some(name)),
+            None => self.loops.len().checked_sub(1)
+        };
+
+        target.ok_or_else(|| match *label {
+            Some(ref name) => Diagnostic::error(&format!("use of undeclared label '{}", name), span),
+            None => Diagnostic::error(&format!("{} outside of a loop", keyword), span)
+        })
+    }
+
+    fn signature_type(&mut self, ty: Option<Type>) -> Ty {
+        match ty {
+            Some(ty) => Ty::Known(ty),
+            None => self.substitution.fresh(Variable::new(Bound::Any, Origin::Signature, F64))
+        }
+    }
+
+    /// Turns the variables left in the signature into the variables of a scheme.
+    fn generalize(&self, args: &[Ty], ret: Ty) -> Scheme {
+        let mut roots = Vec::new();
+        let mut vars = Vec::new();
+        let mut quantify = |ty: Ty| match self.substitution.resolve(ty) {
+            Ty::Known(ty) => Ty::Known(ty),
+            root => {
+                let index = match roots.iter().position(|other| *other == root) {
+                    Some(index) => index,
+                    None => {
+                        roots.push(root);
+                        vars.push(self.substitution.variable(root).unwrap());
+                        roots.len() - 1
+                    }
+                };
+                Ty::Var(index)
+            }
+        };
+
+        let args = args.iter().map(|ty| quantify(*ty)).collect();
+        let ret = quantify(ret);
+        Scheme { vars, args, ret }
+    }
+}
+
+/// Infers the types of every function, checks them against the types written in the
+/// source and fills in the rest of the AST.
+///
+/// Parameter and return types left out are inferred from the body. A named function
+/// whose types are not fully decided by its body is generic: it is compiled with every
+/// open type set to its default (`f64` for parameters) under its own name, and calls
+/// that need other types get an instance of their own, named like `kare.i64`, which
+/// the checker inserts in front of the caller. User-defined operators are never
+/// generic. The checker remembers what it has seen, so it can be fed a program piece
+/// by piece. A function can only be defined once, unless redefinition is allowed.
+#[derive(Clone)]
+pub struct TypeChecker {
+    functions: HashMap<String, Scheme>,
+    templates: HashMap<String, Functions>,
+    instances: HashSet<String>,
+    pending: Vec<ASTNode>,
+    defined: HashSet<String>,
+    redefinition: bool,
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        TypeChecker {
+            functions: HashMap::new(),
+            templates: HashMap::new(),
+            instances: HashSet::new(),
+            pending: Vec::new(),
+            defined: HashSet::new(),
+            redefinition: false,
+        }
+    }
+
+    /// Lets a later definition of a function replace the earlier one, like the REPL does.
+    pub fn allow_redefinition(mut self) -> Self {
+        self.redefinition = true;
+        self
+    }
+
+    /// Signature of `name` as it is compiled under that name.
+    pub fn signature(&self, name: &str) -> Option<Signature> {
+        self.functions.get(name).map(|scheme| scheme.apply(&scheme.defaults()))
+    }
+
+    /// Checks every node, a node with an error is reported and skipped. Instances of
+    /// generic functions are inserted in front of the node that needs them.
+    pub fn check(&mut self, ast: &mut Vec<ASTNode>) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut index = 0;
+
+        while index < ast.len() {
+            let result = match ast[index] {
+                ExternNode(ref mut prototype) => {
+                    self.declare(prototype);
+                    Ok(())
+                },
+                FunctionsNode(ref mut function) => self.define(function)
+            };
+
+            let pending = std::mem::take(&mut self.pending);
+            match result {
+                Ok(()) => {
+                    let count = pending.len();
+                    ast.splice(index..index, pending);
+                    index += count + 1;
+                },
+                Err(diagnostic) => {
+                    for node in pending {
+                        if let FunctionsNode(function) = node {
+                            self.instances.remove(&function.prototype.name);
+                        }
+                    }
+                    diagnostics.push(diagnostic);
+                    index += 1;
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    fn declare(&mut self, prototype: &mut Prototype) {
+        for arg in prototype.args.iter_mut() {
+            arg.1.get_or_insert(F64);
+        }
+        let ret = *prototype.ret_type.get_or_insert(F64);
+
+        let scheme = Scheme {
+            vars: vec![],
+            args: prototype.args.iter().map(|arg| Ty::Known(arg.1.unwrap())).collect(),
+            ret: Ty::Known(ret)
+        };
+        self.functions.insert(prototype.name.clone(), scheme);
+    }
+
+    fn define(&mut self, function: &mut Functions) -> Result<(), Diagnostic> {
+        let name = function.prototype.name.clone();
+        if !self.redefinition && self.defined.contains(&name) {
+            let message = format!("function `{}` is already defined", name);
+            return Err(Diagnostic::error(&message, function.prototype.span));
+        }
+
+        let template = function.clone();
+        let scheme = self.check_function(function, &name)?;
+
+        if !name.is_empty() {
+            // instances of an earlier definition are made again when they are needed
+            let prefix = format!("{}.", name);
+            self.instances.retain(|instance| !instance.starts_with(&prefix));
+
+            if scheme.is_generic() {
+                self.templates.insert(name.clone(), template);
+            } else {
+                self.templates.remove(&name);
+            }
+            self.functions.insert(name.clone(), scheme);
+            self.defined.insert(name);
+        }
+
+        Ok(())
+    }
+
+    /// Checks `function`, which is called `source_name` in its own body, and writes the
+    /// solved types into it.
+    fn check_function(&mut self, function: &mut Functions, source_name: &str) -> Result<Scheme, Diagnostic> {
+        let mut state = Inference {
+            substitution: Substitution::new(),
+            variables: HashMap::new(),
+            loops: Vec::new(),
+            types: Vec::new(),
+            instances: Vec::new(),
+            renames: HashMap::new(),
+            current: (String::new(), String::new(), Vec::new(), Ty::Known(F64)),
+        };
+
+        let prototype = &function.prototype;
+        let anonymous = prototype.name.is_empty();
+        let args: Vec<Ty> = prototype.args.iter().map(|arg| state.signature_type(arg.1)).collect();
+        let ret = match prototype.ret_type {
+            None if anonymous => state.substitution.fresh(Variable::new(Bound::Any, Origin::Unknown, F64)),
+            ty => state.signature_type(ty)
+        };
+
+        for (arg, ty) in prototype.args.iter().zip(args.iter()) {
+            state.variables.insert(arg.0.clone(), (*ty, false));
+        }
+        state.current = (source_name.to_string(), prototype.name.clone(), args.clone(), ret);
+
+        let body = self.infer(&mut state, &function.body)?;
+        state.unify(ret, body, &function.body)?;
+
+        let scheme = if anonymous || prototype.fn_type != Normal {
+            Scheme {
+                vars: vec![],
+                args: args.iter().map(|ty| Ty::Known(state.substitution.finish(*ty))).collect(),
+                ret: Ty::Known(state.substitution.finish(ret))
+            }
+        } else {
+            state.generalize(&args, ret)
+        };
+
+        let mut needed = Vec::new();
+        for (index, callee, vars) in state.instances.iter() {
+            let types: Vec<Type> = vars.iter().map(|ty| state.substitution.finish(*ty)).collect();
+            if types != self.functions[callee].defaults() {
+                state.renames.insert(*index, mangle(callee, &types));
+                needed.push((callee.clone(), types));
+            }
+        }
+
+        let types: Vec<Type> = state.types.iter().map(|ty| state.substitution.finish(*ty)).collect();
+        write_types(&mut function.body, &types, &state.renames, &mut 0);
+        check_literals(&mut function.body)?;
+
+        for (arg, ty) in function.prototype.args.iter_mut().zip(args.iter()) {
+            arg.1 = Some(state.substitution.finish(*ty));
+        }
+        function.prototype.ret_type = Some(state.substitution.finish(ret));
+
+        for (callee, types) in needed {
+            self.instantiate(&callee, &types)?;
+        }
+
+        Ok(scheme)
+    }
+
+    /// Checks the instance of the generic function `name` for `types` unless that was
+    /// done before, and queues it to be inserted into the program.
+    fn instantiate(&mut self, name: &str, types: &[Type]) -> Result<(), Diagnostic> {
+        let instance = mangle(name, types);
+        if !self.instances.insert(instance.clone()) {
+            return Ok(());
+        }
+
+        let signature = self.functions[name].apply(types);
+        let mut function = self.templates[name].clone();
+        function.prototype.name = instance;
+        for (arg, ty) in function.prototype.args.iter_mut().zip(signature.args.iter()) {
+            arg.1 = Some(*ty);
+        }
+        function.prototype.ret_type = Some(signature.ret);
+
+        // the name is taken before checking, so recursive calls find it
+        if let Err(diagnostic) = self.check_function(&mut function, name) {
+            self.instances.remove(&function.prototype.name);
+            return Err(diagnostic);
+        }
+        self.pending.push(FunctionsNode(function));
+        Ok(())
+    }
+
+    /// Infers the type of a condition, which can be of any type but `()`.
+    fn infer_condition(&self, state: &mut Inference, expr: &Expression) -> Result<Ty, Diagnostic> {
+        let ty = self.infer(state, expr)?;
+        if !state.substitution.constrain(ty, Bound::Value) {
+            let message = format!("expected a condition, found {}", state.substitution.describe(ty));
+            return Err(state.misuse(ty, expr, &message, expr.span));
+        }
+        Ok(ty)
+    }
+
+    fn infer(&self, state: &mut Inference, expr: &Expression) -> Result<Ty, Diagnostic> {
+        let span = expr.span;
+
+        let ty = match expr.kind {
+            LiteralExpr(IntegerLiteral(_)) => state.substitution.fresh(Variable::new(Bound::Numeric, Origin::Literal, I64)),
+            LiteralExpr(FloatLiteral(_)) => state.substitution.fresh(Variable::new(Bound::Float, Origin::Literal, F64)),
+            LiteralExpr(BoolLiteral(_)) => Ty::Known(Bool),
+
+            VariableExpr(ref name) => {
+                match state.variables.get(name) {
+                    Some(&(ty, _)) => ty,
+                    None => return Err(Diagnostic::error(&format!("unknown variable name {}", name), span))
+                }
+            },
+
+            UnaryExpr(ref operator, ref operand) => {
+                let ty = self.infer(state, operand)?;
+
+                let name = "unary".to_string() + operator;
+                if let Some(scheme) = self.functions.get(&name) {
+                    state.unify(scheme.args[0], ty, operand)?;
+                    scheme.ret
+                } else if BUILTIN_UNARY_OPERATORS.contains(&operator.as_str()) {
+                    if !state.substitution.constrain(ty, operand_bound(operator)) {
+                        let message = match operator.as_str() {
+                            "-" => format!("cannot negate a value of type {}", state.substitution.describe(ty)),
+                            _ => format!("operator {} is not defined for type {}", operator, state.substitution.describe(ty))
+                        };
+                        return Err(state.misuse(ty, operand, &message, span));
+                    }
+                    if operator == "!" { Ty::Known(Bool) } else { ty }
+                } else {
+                    return Err(Diagnostic::error(&format!("unknown unary operator {}", operator), span));
+                }
+            },
+
+            BinaryExpr(ref operator, ref lhs, ref rhs) if LOGICAL_OPERATORS.contains(&operator.as_str()) => {
+                self.infer_condition(state, lhs)?;
+                self.infer_condition(state, rhs)?;
+                Ty::Known(Bool)
+            },
+
+            BinaryExpr(ref operator, ref lhs, ref rhs) => {
+                let lhs_ty = self.infer(state, lhs)?;
+                let rhs_ty = self.infer(state, rhs)?;
+
+                if BUILTIN_BINARY_OPERATORS.contains(&operator.as_str()) {
+                    if !state.substitution.constrain(lhs_ty, operand_bound(operator)) {
+                        let message = format!("operator {} is not defined for type {}", operator, state.substitution.describe(lhs_ty));
+                        return Err(state.misuse(lhs_ty, lhs, &message, span));
+                    }
+                    state.unify(lhs_ty, rhs_ty, rhs)?;
+
+                    if COMPARISON_OPERATORS.contains(&operator.as_str()) { Ty::Known(Bool) } else { lhs_ty }
+                } else {
+                    let name = "binary".to_string() + operator;
+                    let scheme = match self.functions.get(&name) {
+                        Some(scheme) => scheme,
+                        None => return Err(Diagnostic::error(&format!("unknown binary operator {}", operator), span))
+                    };
+
+                    state.unify(scheme.args[0], lhs_ty, lhs)?;
+                    state.unify(scheme.args[1], rhs_ty, rhs)?;
+                    scheme.ret
+                }
+            },
+
+            ConditionalExpr{ref cond_expr, ref then_expr, ref else_expr} => {
+                self.infer_condition(state, cond_expr)?;
+                let then_ty = self.infer(state, then_expr)?;
+
+                match *else_expr {
+                    Some(ref else_expr) => {
+                        let else_ty = self.infer(state, else_expr)?;
+
+                        if missing_else(else_expr).is_some() {
+                            // an else if without else makes the whole chain a statement
+                            Ty::Known(Unit)
+                        } else if missing_else(then_expr).is_some() {
+                            state.unify(else_ty, then_ty, then_expr)?;
+                            else_ty
+                        } else {
+                            state.unify(then_ty, else_ty, else_expr)?;
+                            then_ty
+                        }
+                    },
+                    None => Ty::Known(Unit)
+                }
+            },
+
+            LoopExpr{ref label, ref var_name, ref start_expr, ref end_expr, ref step_expr, ref body_expr} => {
+                let ty = self.infer(state, start_expr)?;
+                if !state.substitution.constrain(ty, Bound::Numeric) {
+                    let message = format!("loop variable cannot have type {}", state.substitution.describe(ty));
+                    return Err(state.misuse(ty, start_expr, &message, start_expr.span));
+                }
+
+                let old_value = state.variables.insert(var_name.clone(), (ty, false));
+
+                let result = self.infer_condition(state, end_expr)
+                    .and_then(|_| self.infer(state, step_expr))
+                    .and_then(|step_ty| state.unify(ty, step_ty, step_expr))
+                    .and_then(|_| {
+                        state.loops.push((label.clone(), None));
+                        let result = self.infer(state, body_expr);
+                        state.loops.pop();
+                        result
+                    });
+
+                match old_value {
+                    Some(value) => {state.variables.insert(var_name.clone(), value);},
+                    None => {state.variables.remove(var_name);}
+                };
+
+                result?;
+                Ty::Known(F64)
+            },
+
+            WhileExpr{ref label, ref cond_expr, ref body_expr} => {
+                if let Some(ref cond_expr) = *cond_expr {
+                    self.infer_condition(state, cond_expr)?;
+                }
+
+                // a loop without a break value gives the zero of its type
+                let value_ty = match *cond_expr {
+                    Some(_) => None,
+                    None => Some(state.substitution.fresh(Variable::new(Bound::Any, Origin::Unknown, F64)))
+                };
+
+                state.loops.push((label.clone(), value_ty));
+                let result = self.infer(state, body_expr);
+                state.loops.pop();
+
+                result?;
+                value_ty.unwrap_or(Ty::Known(F64))
+            },
+
+            BreakExpr{ref label, ref value} => {
+                let target = state.loop_target("break", label, span)?;
+
+                if let Some(ref value) = *value {
+                    let ty = self.infer(state, value)?;
+                    match state.loops[target].1 {
+                        Some(loop_ty) => state.unify(loop_ty, ty, value)?,
+                        None => return Err(Diagnostic::error("only loop can be left with a value, not while or for", value.span))
+                    }
+                }
+
+                // break does not give a value to where it is, any type fits
+                state.substitution.fresh(Variable::new(Bound::Any, Origin::Unknown, F64))
+            },
+
+            ContinueExpr(ref label) => {
+                state.loop_target("continue", label, span)?;
+                state.substitution.fresh(Variable::new(Bound::Any, Origin::Unknown, F64))
+            },
+
+            ReturnExpr(ref value) => {
+                let ty = self.infer(state, value)?;
+                state.unify(state.current.3, ty, value)?;
+                state.substitution.fresh(Variable::new(Bound::Any, Origin::Unknown, F64))
+            },
+
+            VarExpr{ref vars, ref body_expr} => {
+                let mut old_bindings = Vec::new();
+                let mut result = Ok(Ty::Known(F64));
+
+                for (name, ty, init_expr, mutable) in vars.iter() {
+                    result = self.infer(state, init_expr).and_then(|init_ty| {
+                        if let Some(ty) = *ty {
+                            state.unify(Ty::Known(ty), init_ty, init_expr)?;
+                        }
+                        Ok(init_ty)
+                    });
+
+                    match result {
+                        Ok(init_ty) => old_bindings.push((name.clone(), state.variables.insert(name.clone(), (init_ty, *mutable)))),
+                        Err(_) => break
+                    }
+                }
+
+                let result = result.and_then(|_| self.infer(state, body_expr));
+
+                for (name, old_value) in old_bindings.into_iter().rev() {
+                    match old_value {
+                        Some(value) => {state.variables.insert(name, value);},
+                        None => {state.variables.remove(&name);}
+                    };
+                }
+
+                result?
+            },
+
+            AssignExpr(ref name, ref value) => {
+                let value_ty = self.infer(state, value)?;
+
+                let ty = match state.variables.get(name) {
+                    Some(&(ty, true)) => ty,
+                    Some(_) => return Err(Diagnostic::error(&format!("cannot assign to immutable variable {}", name), span)),
+                    None => return Err(Diagnostic::error(&format!("unknown variable name {}", name), span))
+                };
+                state.unify(ty, value_ty, value)?;
+                ty
+            },
+
+            BlockExpr(ref exprs) => {
+                let mut ty = Ty::Known(F64);
+                for expr in exprs.iter() {
+                    ty = self.infer(state, expr)?;
+                }
+                ty
+            },
+
+            CallExpr(ref name, ref args) => {
+                let mut arg_types = Vec::new();
+                for arg in args.iter() {
+                    arg_types.push(self.infer(state, arg)?);
+                }
+
+                let (params, ret) = if *name == state.current.0 {
+                    if state.current.0 != state.current.1 {
+                        state.renames.insert(state.types.len(), state.current.1.clone());
+                    }
+                    (state.current.2.clone(), state.current.3)
+                } else {
+                    let scheme = match self.functions.get(name) {
+                        Some(scheme) => scheme,
+                        None => return Err(Diagnostic::error(&format!("unknown function referenced {}", name), span))
+                    };
+
+                    let vars: Vec<Ty> = scheme.vars.iter().map(|var| state.substitution.fresh(*var)).collect();
+                    let instantiate = |ty: Ty| match ty {
+                        Ty::Known(ty) => Ty::Known(ty),
+                        Ty::Var(index) => vars[index]
+                    };
+                    let params = scheme.args.iter().map(|ty| instantiate(*ty)).collect();
+                    let ret = instantiate(scheme.ret);
+
+                    if scheme.is_generic() {
+                        state.instances.push((state.types.len(), name.clone(), vars));
+                    }
+                    (params, ret)
+                };
+
+                if params.len() != args.len() {
+                    return Err(Diagnostic::error(&format!(
+                        "{} takes {} argument(s) but {} were given", name, params.len(), args.len()), span));
+                }
+
+                for ((param, arg_ty), arg) in params.iter().zip(arg_types).zip(args.iter()) {
+                    state.unify(*param, arg_ty, arg)?;
+                }
+
+                ret
+            }
+        };
+
+        state.types.push(ty);
+        Ok(ty)
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
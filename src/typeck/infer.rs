@@ -0,0 +1,192 @@
+use crate::ast::types::{Type, I64, F64, Unit};
+
+/// What a type variable is allowed to become.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Bound {
+    Any,
+    /// Anything but `()`, which cannot be tested or compared.
+    Value,
+    Numeric,
+    Integer,
+    Float
+}
+
+impl Bound {
+    pub fn admits(self, ty: Type) -> bool {
+        match self {
+            Bound::Any => true,
+            Bound::Value => ty != Unit,
+            Bound::Numeric => ty.is_numeric(),
+            Bound::Integer => ty.is_integer(),
+            Bound::Float => ty.is_float()
+        }
+    }
+
+    /// The bound admitting what both admit, `None` if no type is left.
+    fn join(self, other: Bound) -> Option<Bound> {
+        match (self, other) {
+            (Bound::Integer, Bound::Float) | (Bound::Float, Bound::Integer) => None,
+            _ => Some(self.max(other))
+        }
+    }
+}
+
+/// Where a type variable comes from. When two variables are unified the fallback of
+/// the stronger origin is kept, so a parameter stays `f64` even if it is compared
+/// with an integer literal.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Origin {
+    Unknown,
+    Literal,
+    Signature
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Variable {
+    pub bound: Bound,
+    pub origin: Origin,
+    pub fallback: Type
+}
+
+impl Variable {
+    pub fn new(bound: Bound, origin: Origin, fallback: Type) -> Variable {
+        Variable { bound, origin, fallback }
+    }
+
+    fn merge(self, other: Variable) -> Option<Variable> {
+        let preferred = if other.origin > self.origin { other } else { self };
+        Some(Variable {
+            bound: self.bound.join(other.bound)?,
+            ..preferred
+        })
+    }
+
+    /// The type this variable gets when nothing decides it.
+    pub fn default_type(&self) -> Type {
+        if self.bound.admits(self.fallback) {
+            self.fallback
+        } else if self.bound == Bound::Integer {
+            I64
+        } else {
+            F64
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Ty {
+    Known(Type),
+    Var(usize)
+}
+
+enum Slot {
+    Unbound(Variable),
+    Link(Ty)
+}
+
+/// Union-find table of the type variables of one function.
+pub struct Substitution {
+    slots: Vec<Slot>
+}
+
+impl Substitution {
+    pub fn new() -> Substitution {
+        Substitution { slots: Vec::new() }
+    }
+
+    pub fn fresh(&mut self, variable: Variable) -> Ty {
+        self.slots.push(Slot::Unbound(variable));
+        Ty::Var(self.slots.len() - 1)
+    }
+
+    /// Follows links until a known type or an unbound variable is reached.
+    pub fn resolve(&self, ty: Ty) -> Ty {
+        let mut ty = ty;
+        while let Ty::Var(index) = ty {
+            match self.slots[index] {
+                Slot::Link(next) => ty = next,
+                Slot::Unbound(_) => break
+            }
+        }
+        ty
+    }
+
+    pub fn variable(&self, ty: Ty) -> Option<Variable> {
+        match self.resolve(ty) {
+            Ty::Var(index) => match self.slots[index] {
+                Slot::Unbound(variable) => Some(variable),
+                Slot::Link(_) => unreachable!()
+            },
+            Ty::Known(_) => None
+        }
+    }
+
+    pub fn describe(&self, ty: Ty) -> String {
+        match self.resolve(ty) {
+            Ty::Known(ty) => ty.to_string(),
+            ty => match self.variable(ty).unwrap().bound {
+                Bound::Any | Bound::Value => "_".to_string(),
+                Bound::Numeric => "{number}".to_string(),
+                Bound::Integer => "{integer}".to_string(),
+                Bound::Float => "{float}".to_string()
+            }
+        }
+    }
+
+    /// Makes `expected` and `found` the same type, the error names both sides.
+    pub fn unify(&mut self, expected: Ty, found: Ty) -> Result<(), String> {
+        let error = |this: &Substitution| format!("expected {}, found {}", this.describe(expected), this.describe(found));
+
+        match (self.resolve(expected), self.resolve(found)) {
+            (Ty::Known(a), Ty::Known(b)) if a == b => Ok(()),
+            (Ty::Known(_), Ty::Known(_)) => Err(error(self)),
+            (Ty::Var(a), Ty::Var(b)) if a == b => Ok(()),
+            (Ty::Var(a), Ty::Var(b)) => {
+                let merged = match self.variable(Ty::Var(a)).unwrap().merge(self.variable(Ty::Var(b)).unwrap()) {
+                    Some(merged) => merged,
+                    None => return Err(error(self))
+                };
+                self.slots[a] = Slot::Link(Ty::Var(b));
+                self.slots[b] = Slot::Unbound(merged);
+                Ok(())
+            },
+            (Ty::Var(index), Ty::Known(ty)) | (Ty::Known(ty), Ty::Var(index)) => {
+                if !self.variable(Ty::Var(index)).unwrap().bound.admits(ty) {
+                    return Err(error(self));
+                }
+                self.slots[index] = Slot::Link(Ty::Known(ty));
+                Ok(())
+            }
+        }
+    }
+
+    /// Narrows `ty` down to `bound`, false if it is a known type outside of it.
+    pub fn constrain(&mut self, ty: Ty, bound: Bound) -> bool {
+        match self.resolve(ty) {
+            Ty::Known(ty) => bound.admits(ty),
+            Ty::Var(index) => {
+                let mut variable = self.variable(Ty::Var(index)).unwrap();
+                variable.bound = match variable.bound.join(bound) {
+                    Some(bound) => bound,
+                    None => return false
+                };
+                self.slots[index] = Slot::Unbound(variable);
+                true
+            }
+        }
+    }
+
+    /// Concrete type of `ty`, variables nothing has decided get their default.
+    pub fn finish(&self, ty: Ty) -> Type {
+        match self.resolve(ty) {
+            Ty::Known(ty) => ty,
+            ty => self.variable(ty).unwrap().default_type()
+        }
+    }
+}
+
+impl Default for Substitution {
+    fn default() -> Self {
+        Self::new()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,2 @@
+pub mod checker;
+pub mod infer;
\ No newline at end of file